@@ -0,0 +1,98 @@
+use geometry::{Matrix4, Vertex3};
+
+/// The smallest box with faces along the axes that holds a set of points.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vertex3<f32>,
+    pub max: Vertex3<f32>,
+}
+
+impl BoundingBox {
+    /// None when there are no points.
+    pub fn from_points(points: &[Vertex3<f32>]) -> Option<BoundingBox> {
+        let first = *points.first()?;
+        let mut bounds = BoundingBox { min: first, max: first };
+        for p in points.iter() {
+            bounds.min = Vertex3::init(bounds.min.x.min(p.x), bounds.min.y.min(p.y), bounds.min.z.min(p.z));
+            bounds.max = Vertex3::init(bounds.max.x.max(p.x), bounds.max.y.max(p.y), bounds.max.z.max(p.z));
+        }
+        Some(bounds)
+    }
+
+    pub fn center(&self) -> Vertex3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Length of the box along each axis.
+    pub fn size(&self) -> Vertex3<f32> {
+        self.max - self.min
+    }
+}
+
+/// A sphere holding a set of points.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vertex3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// A sphere around the center of the points' bounding box, which is
+    /// not the smallest possible but close to it for most models. None when
+    /// there are no points.
+    pub fn from_points(points: &[Vertex3<f32>]) -> Option<BoundingSphere> {
+        let center = BoundingBox::from_points(points)?.center();
+        let radius = points.iter().map(|&p| (p - center).norm()).fold(0.0, f32::max);
+        Some(BoundingSphere { center, radius })
+    }
+
+    /// Moves the center of the sphere to the origin and scales it to a
+    /// radius of 1. Points are left alone when the sphere has no size.
+    pub fn normalize_transform(&self) -> Matrix4 {
+        let scale = if self.radius > 0.0 { 1.0 / self.radius } else { 1.0 };
+        let mut m = Matrix4::identity();
+        for i in 0..3 {
+            m.set(i, i, scale);
+        }
+        m.set(0, 3, -self.center.x * scale);
+        m.set(1, 3, -self.center.y * scale);
+        m.set(2, 3, -self.center.z * scale);
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_of_points() {
+        let points = [
+            Vertex3::init(1.0, 2.0, 3.0),
+            Vertex3::init(-1.0, 4.0, 3.0),
+            Vertex3::init(3.0, 2.0, 1.0),
+        ];
+        let bounds = BoundingBox::from_points(&points).unwrap();
+        assert!(bounds.min == Vertex3::init(-1.0, 2.0, 1.0));
+        assert!(bounds.max == Vertex3::init(3.0, 4.0, 3.0));
+        assert!(bounds.center() == Vertex3::init(1.0, 3.0, 2.0));
+        assert!(bounds.size() == Vertex3::init(4.0, 2.0, 2.0));
+
+        let sphere = BoundingSphere::from_points(&points).unwrap();
+        assert!(sphere.center == bounds.center());
+        assert!(points.iter().all(|&p| (p - sphere.center).norm() <= sphere.radius));
+        assert!(BoundingBox::from_points(&[]).is_none());
+    }
+
+    #[test]
+    fn normalize_transform_fits_the_unit_sphere() {
+        let sphere = BoundingSphere {
+            center: Vertex3::init(10.0, 0.0, -4.0),
+            radius: 5.0,
+        };
+        let m = sphere.normalize_transform();
+        let moved = |p: Vertex3<f32>| (m.clone() * p.to_homogeneous()).to_vector();
+        assert!(moved(sphere.center) == Vertex3::new());
+        assert!(moved(Vertex3::init(15.0, 0.0, -4.0)) == Vertex3::init(1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,365 @@
+use geometry::Vertex3;
+use normals::NormalSettings;
+use renderer::{Blend, Interpolation, NormalSpace, Shading};
+use shadow::ShadowSettings;
+use std::path::PathBuf;
+use std::thread;
+
+pub const USAGE: &str = "usage: pocket-renderer render --model <path.obj|gltf|glb|stl|ply> --out <frame.png> \
+[--width <px>] [--height <px>] [--eye <x,y,z>] [--light <x,y,z>] [--shading gouraud|normal|tangent] [--interpolation perspective|affine] [--shadows] [--shadow-bias <depth>] [--shadow-pcf <radius>] [--threads <n>] [--mesh <name>]... [--crease-angle <degrees>] [--anisotropy <samples>] [--blend alpha|additive|multiply|premultiplied] [--alpha-cutoff <0-1>] [--frame]
+       pocket-renderer convert <model> <out.obj|ply> [--crease-angle <degrees>]";
+
+/// Options for rendering a single frame without opening a window.
+#[derive(Debug)]
+pub struct RenderOptions {
+    pub model: PathBuf,
+    pub out: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub eye: Vertex3<f32>,
+    pub light_dir: Vertex3<f32>,
+    pub shading: Shading,
+    pub interpolation: Interpolation,
+    pub shadows: Option<ShadowSettings>,
+    pub threads: usize,
+    /// Names of the meshes to draw. Everything is drawn when empty.
+    pub meshes: Vec<String>,
+    /// Used for models without vertex normals.
+    pub normals: NormalSettings,
+    /// Aims and zooms the camera to fit the model in the view. `eye` is
+    /// then taken relative to the model's bounding sphere, in multiples of
+    /// its radius, rather than in model space.
+    pub frame: bool,
+    /// Most texture samples taken per pixel along surfaces seen at a
+    /// grazing angle. 1 turns anisotropic filtering off.
+    pub anisotropy: u32,
+    /// Used for faces that aren't fully opaque.
+    pub blend: Blend,
+    pub alpha_cutoff: Option<f32>,
+}
+
+impl RenderOptions {
+    /// Parses the arguments that follow the `render` subcommand.
+    pub fn parse(args: &[String]) -> Result<RenderOptions, String> {
+        let mut model = None;
+        let mut out = None;
+        let mut width = 1024;
+        let mut height = 768;
+        let mut eye = Vertex3::init(1.0, 1.0, 3.0);
+        let mut light_dir = Vertex3::init(1.0, 1.0, 1.0);
+        let mut shading = Shading::Gourad;
+        let mut interpolation = Interpolation::Perspective;
+        let mut shadows: Option<ShadowSettings> = None;
+        let mut threads = available_threads();
+        let mut meshes = Vec::new();
+        let mut normals = NormalSettings::new();
+        let mut frame = false;
+        let mut anisotropy = 1;
+        let mut blend = Blend::Alpha;
+        let mut alpha_cutoff = None;
+
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--shadows" {
+                shadows = shadows.or_else(|| Some(ShadowSettings::new()));
+                continue;
+            }
+            if flag == "--frame" {
+                frame = true;
+                continue;
+            }
+            let value = match iter.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", flag)),
+            };
+            match flag.as_str() {
+                "--model" => model = Some(PathBuf::from(value)),
+                "--out" => out = Some(PathBuf::from(value)),
+                "--width" => width = parse_dimension(flag, value)?,
+                "--height" => height = parse_dimension(flag, value)?,
+                "--eye" => eye = parse_vertex(value)?,
+                "--light" => light_dir = parse_vertex(value)?,
+                "--shading" => shading = parse_shading(value)?,
+                "--interpolation" => interpolation = parse_interpolation(value)?,
+                "--threads" => threads = parse_dimension(flag, value)? as usize,
+                "--mesh" => meshes.push(value.to_string()),
+                "--crease-angle" => normals.crease_angle = Some(parse_angle(flag, value)?),
+                "--anisotropy" => anisotropy = parse_dimension(flag, value)?,
+                "--blend" => blend = parse_blend(value)?,
+                "--alpha-cutoff" => alpha_cutoff = Some(parse_alpha(flag, value)?),
+                "--shadow-bias" => {
+                    let settings = shadows.get_or_insert_with(ShadowSettings::new);
+                    settings.bias = value
+                        .parse()
+                        .map_err(|_| format!("{} must be a number, got {}", flag, value))?;
+                }
+                "--shadow-pcf" => {
+                    let settings = shadows.get_or_insert_with(ShadowSettings::new);
+                    settings.pcf_radius = value
+                        .parse()
+                        .map_err(|_| format!("{} must be a whole number, got {}", flag, value))?;
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+
+        Ok(RenderOptions {
+            model: model.ok_or("--model is required")?,
+            out: out.ok_or("--out is required")?,
+            width,
+            height,
+            eye,
+            light_dir,
+            shading,
+            interpolation,
+            shadows,
+            threads,
+            meshes,
+            normals,
+            frame,
+            anisotropy,
+            blend,
+            alpha_cutoff,
+        })
+    }
+}
+
+/// Options for converting a model to another format.
+#[derive(Debug)]
+pub struct ConvertOptions {
+    pub model: PathBuf,
+    pub out: PathBuf,
+    /// Used for models without vertex normals.
+    pub normals: NormalSettings,
+}
+
+impl ConvertOptions {
+    /// Parses the arguments that follow the `convert` subcommand.
+    pub fn parse(args: &[String]) -> Result<ConvertOptions, String> {
+        let mut paths = Vec::new();
+        let mut normals = NormalSettings::new();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                paths.push(PathBuf::from(arg));
+                continue;
+            }
+            let value = match iter.next() {
+                Some(value) => value,
+                None => return Err(format!("missing value for {}", arg)),
+            };
+            match arg.as_str() {
+                "--crease-angle" => normals.crease_angle = Some(parse_angle(arg, value)?),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+
+        if paths.len() != 2 {
+            return Err("expected a model and an output path".to_string());
+        }
+        let out = paths.pop().unwrap();
+        let model = paths.pop().unwrap();
+        match out.extension().and_then(|extension| extension.to_str()) {
+            Some("obj") | Some("ply") => {}
+            _ => return Err(format!("can only write .obj and .ply files, not {}", out.display())),
+        }
+        Ok(ConvertOptions { model, out, normals })
+    }
+}
+
+/// Number of threads to rasterize with when none is given.
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+fn parse_dimension(flag: &str, value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(0) | Err(_) => Err(format!("{} must be a positive integer, got {}", flag, value)),
+        Ok(n) => Ok(n),
+    }
+}
+
+fn parse_angle(flag: &str, value: &str) -> Result<f32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} must be a number, got {}", flag, value))
+}
+
+fn parse_shading(value: &str) -> Result<Shading, String> {
+    match value {
+        "gouraud" => Ok(Shading::Gourad),
+        "normal" => Ok(Shading::NormalMapped(NormalSpace::Object)),
+        "tangent" => Ok(Shading::NormalMapped(NormalSpace::Tangent)),
+        _ => Err(format!("unknown shading {}", value)),
+    }
+}
+
+fn parse_interpolation(value: &str) -> Result<Interpolation, String> {
+    match value {
+        "perspective" => Ok(Interpolation::Perspective),
+        "affine" => Ok(Interpolation::Affine),
+        _ => Err(format!("unknown interpolation {}", value)),
+    }
+}
+
+fn parse_blend(value: &str) -> Result<Blend, String> {
+    match value {
+        "alpha" => Ok(Blend::Alpha),
+        "additive" => Ok(Blend::Additive),
+        "multiply" => Ok(Blend::Multiply),
+        "premultiplied" => Ok(Blend::Premultiplied),
+        _ => Err(format!("unknown blend mode {}", value)),
+    }
+}
+
+fn parse_alpha(flag: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(alpha) if (0.0..=1.0).contains(&alpha) => Ok(alpha),
+        _ => Err(format!("{} must be a number from 0 to 1, got {}", flag, value)),
+    }
+}
+
+fn parse_vertex(value: &str) -> Result<Vertex3<f32>, String> {
+    let coords: Vec<f32> = value
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("expected x,y,z but got {}", value))?;
+    if coords.len() != 3 {
+        return Err(format!("expected x,y,z but got {}", value));
+    }
+    Ok(Vertex3::init(coords[0], coords[1], coords[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_render_options() {
+        let options = RenderOptions::parse(&args(&[
+            "--model", "head.obj", "--out", "frame.png", "--width", "1920", "--height", "1080",
+            "--eye", "0,1.5,-2", "--light", "0,0,1",
+        ]))
+        .unwrap();
+        assert!(options.model == Path::new("head.obj"));
+        assert!(options.out == Path::new("frame.png"));
+        assert!(options.width == 1920 && options.height == 1080);
+        assert!(options.eye == Vertex3::init(0.0, 1.5, -2.0));
+        assert!(options.light_dir == Vertex3::init(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn parse_render_options_uses_defaults() {
+        let options = RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png"])).unwrap();
+        assert!(options.width == 1024 && options.height == 768);
+        assert!(options.eye == Vertex3::init(1.0, 1.0, 3.0));
+        assert!(options.shading == Shading::Gourad);
+        assert!(options.shadows.is_none());
+        assert!(options.interpolation == Interpolation::Perspective);
+        assert!(options.threads == available_threads());
+        assert!(options.meshes.is_empty());
+        assert!(!options.frame);
+        assert!(options.anisotropy == 1);
+        assert!(options.blend == Blend::Alpha);
+        assert!(options.alpha_cutoff.is_none());
+    }
+
+    #[test]
+    fn parse_render_options_frame() {
+        let options = RenderOptions::parse(&args(&["--frame", "--model", "a.obj", "--out", "a.png"])).unwrap();
+        assert!(options.frame);
+    }
+
+    #[test]
+    fn parse_render_options_interpolation() {
+        let parse = |value| {
+            RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--interpolation", value]))
+        };
+        assert!(parse("affine").unwrap().interpolation == Interpolation::Affine);
+        assert!(parse("linear").is_err());
+    }
+
+    #[test]
+    fn parse_render_options_shadows() {
+        let parse = |extra: &[&str]| {
+            let mut list = vec!["--model", "a.obj", "--out", "a.png"];
+            list.extend_from_slice(extra);
+            RenderOptions::parse(&args(&list))
+        };
+        assert!(parse(&["--shadows"]).unwrap().shadows == Some(ShadowSettings::new()));
+        let tuned = parse(&["--shadow-pcf", "2", "--shadow-bias", "1.5"]).unwrap().shadows.unwrap();
+        assert!(tuned.pcf_radius == 2 && tuned.bias == 1.5);
+        assert!(parse(&["--shadow-pcf", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parse_render_options_shading() {
+        let parse = |shading| {
+            RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--shading", shading]))
+        };
+        assert!(parse("normal").unwrap().shading == Shading::NormalMapped(NormalSpace::Object));
+        assert!(parse("tangent").unwrap().shading == Shading::NormalMapped(NormalSpace::Tangent));
+        assert!(parse("phong").is_err());
+    }
+
+    #[test]
+    fn parse_render_options_meshes() {
+        let options = RenderOptions::parse(&args(&[
+            "--model", "a.obj", "--out", "a.png", "--mesh", "head", "--mesh", "left eye",
+        ]))
+        .unwrap();
+        assert!(options.meshes == vec!["head".to_string(), "left eye".to_string()]);
+    }
+
+    #[test]
+    fn parse_render_options_crease_angle() {
+        let parse = |angle| RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--crease-angle", angle]));
+        assert!(parse("45").unwrap().normals.crease_angle == Some(45.0));
+        assert!(parse("sharp").is_err());
+    }
+
+    #[test]
+    fn parse_render_options_anisotropy() {
+        let parse = |samples| RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--anisotropy", samples]));
+        assert!(parse("8").unwrap().anisotropy == 8);
+        assert!(parse("0").is_err());
+    }
+
+    #[test]
+    fn parse_render_options_transparency() {
+        let parse = |flag, value| RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", flag, value]));
+        assert!(parse("--blend", "additive").unwrap().blend == Blend::Additive);
+        assert!(parse("--blend", "premultiplied").unwrap().blend == Blend::Premultiplied);
+        assert!(parse("--blend", "screen").is_err());
+        assert!(parse("--alpha-cutoff", "0.5").unwrap().alpha_cutoff == Some(0.5));
+        assert!(parse("--alpha-cutoff", "2").is_err());
+    }
+
+    #[test]
+    fn parse_convert_options() {
+        let options = ConvertOptions::parse(&args(&["scan.ply", "--crease-angle", "30", "scan.obj"])).unwrap();
+        assert!(options.model == Path::new("scan.ply"));
+        assert!(options.out == Path::new("scan.obj"));
+        assert!(options.normals.crease_angle == Some(30.0));
+        assert!(ConvertOptions::parse(&args(&["scan.ply"])).is_err());
+        assert!(ConvertOptions::parse(&args(&["scan.ply", "scan.stl"])).is_err());
+        assert!(ConvertOptions::parse(&args(&["a.obj", "b.ply", "--bogus", "1"])).is_err());
+    }
+
+    #[test]
+    fn parse_render_options_rejects_bad_input() {
+        assert!(RenderOptions::parse(&args(&["--out", "a.png"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--model", "a.obj", "--out"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--eye", "1,2"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--width", "0"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--bogus", "1"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--model", "a.obj", "--out", "a.png", "--threads", "0"])).is_err());
+    }
+}
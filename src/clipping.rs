@@ -0,0 +1,183 @@
+use geometry::{Matrix4, Vertex3, Vertex4};
+use std::vec::Vec;
+
+/// A corner of a clipped polygon. `bar` holds its barycentric coordinates
+/// with respect to the original, unclipped triangle so shaders can keep
+/// interpolating the original per-vertex attributes.
+#[derive(Debug, Copy, Clone)]
+pub struct ClipVertex {
+    pub position: Vertex4<f32>,
+    pub bar: Vertex3<f32>,
+}
+
+impl ClipVertex {
+    fn lerp(a: ClipVertex, b: ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: a.position + (b.position - a.position) * t,
+            bar: a.bar + (b.bar - a.bar) * t,
+        }
+    }
+}
+
+/// The six planes bounding the visible volume. Each plane is stored as a
+/// 4-vector `p` so that a clip space point `v` is inside when `p * v >= 0`.
+///
+/// Clip space here is everything up to, but not including, the perspective
+/// divide: viewport * projection * model_view. The side planes therefore
+/// sit on the edges of the render target.
+pub struct Frustum {
+    pub planes: [Vertex4<f32>; 6],
+}
+
+impl Frustum {
+    /// `clip_from_view` is viewport * projection, with the eye sitting
+    /// `eye_distance` along the view space z axis as set up by
+    /// `renderer::projection`. `near` and `far` are distances from the eye.
+    pub fn new(
+        width: u32,
+        height: u32,
+        clip_from_view: &Matrix4,
+        eye_distance: f32,
+        near: f32,
+        far: f32,
+    ) -> Frustum {
+        let (width, height) = (width as f32, height as f32);
+        // Near and far are easy to state in view space, where the distance
+        // from the eye is `eye_distance - z`. Planes move into clip space by
+        // the inverse-transpose of the matrix that moves points there.
+        let to_clip = clip_from_view
+            .invert_transpose()
+            .expect("the viewport and projection must be invertible");
+        let near_plane = Vertex4::init(0.0, 0.0, -1.0, eye_distance - near);
+        let far_plane = Vertex4::init(0.0, 0.0, 1.0, far - eye_distance);
+        Frustum {
+            planes: [
+                // left and right: 0 <= x <= width * w
+                Vertex4::init(1.0, 0.0, 0.0, 0.0),
+                Vertex4::init(-1.0, 0.0, 0.0, width),
+                // bottom and top: 0 <= y <= height * w
+                Vertex4::init(0.0, 1.0, 0.0, 0.0),
+                Vertex4::init(0.0, -1.0, 0.0, height),
+                to_clip.clone() * near_plane,
+                to_clip * far_plane,
+            ],
+        }
+    }
+
+    pub fn contains(&self, v: Vertex4<f32>) -> bool {
+        self.planes.iter().all(|&plane| plane * v >= 0.0)
+    }
+
+    /// Clips a triangle against every plane, returning the corners of the
+    /// convex polygon that remains. The result is empty when the triangle
+    /// is entirely outside.
+    pub fn clip_triangle(&self, verts: &[Vertex4<f32>; 3]) -> Vec<ClipVertex> {
+        let mut polygon = vec![
+            ClipVertex {
+                position: verts[0],
+                bar: Vertex3::init(1.0, 0.0, 0.0),
+            },
+            ClipVertex {
+                position: verts[1],
+                bar: Vertex3::init(0.0, 1.0, 0.0),
+            },
+            ClipVertex {
+                position: verts[2],
+                bar: Vertex3::init(0.0, 0.0, 1.0),
+            },
+        ];
+        for &plane in self.planes.iter() {
+            polygon = clip_polygon(&polygon, plane);
+            if polygon.is_empty() {
+                break;
+            }
+        }
+        polygon
+    }
+}
+
+// One Sutherland–Hodgman step: keeps the part of `polygon` on the inside of
+// `plane`, adding a new corner wherever an edge crosses it.
+fn clip_polygon(polygon: &[ClipVertex], plane: Vertex4<f32>) -> Vec<ClipVertex> {
+    let mut output = Vec::with_capacity(polygon.len() + 1);
+    for (i, &current) in polygon.iter().enumerate() {
+        let next = polygon[(i + 1) % polygon.len()];
+        let d_current = plane * current.position;
+        let d_next = plane * next.position;
+        if d_current >= 0.0 {
+            output.push(current);
+        }
+        if (d_current >= 0.0) != (d_next >= 0.0) {
+            let t = d_current / (d_current - d_next);
+            output.push(ClipVertex::lerp(current, next, t));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer;
+
+    // the eye sits 2 units from the center, looking down -z
+    fn clip_from_view() -> Matrix4 {
+        let eye = Vertex3::init(0.0, 0.0, 2.0);
+        renderer::viewport(0, 0, 100, 100, 255) * renderer::projection(eye, Vertex3::new())
+    }
+
+    fn frustum() -> Frustum {
+        Frustum::new(100, 100, &clip_from_view(), 2.0, 0.5, 10.0)
+    }
+
+    fn clip(x: f32, y: f32, z: f32) -> Vertex4<f32> {
+        clip_from_view() * Vertex3::init(x, y, z).to_homogeneous()
+    }
+
+    #[test]
+    fn triangle_inside_is_unchanged() {
+        let verts = [clip(-0.5, -0.5, 0.0), clip(0.5, -0.5, 0.0), clip(0.0, 0.5, 0.0)];
+        let polygon = frustum().clip_triangle(&verts);
+        assert!(polygon.len() == 3);
+        assert!(polygon[0].position == verts[0]);
+        assert!(polygon[2].bar == Vertex3::init(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn triangle_behind_the_eye_is_dropped() {
+        let verts = [clip(-0.5, -0.5, 3.0), clip(0.5, -0.5, 3.0), clip(0.0, 0.5, 4.0)];
+        assert!(frustum().clip_triangle(&verts).is_empty());
+    }
+
+    #[test]
+    fn triangle_beyond_far_plane_is_dropped() {
+        let verts = [clip(-0.5, -0.5, -9.0), clip(0.5, -0.5, -9.0), clip(0.0, 0.5, -9.0)];
+        assert!(frustum().clip_triangle(&verts).is_empty());
+    }
+
+    #[test]
+    fn triangle_crossing_near_plane_is_split() {
+        // the second corner is behind the eye, the others in front of it
+        let verts = [clip(-0.2, -0.2, 0.0), clip(0.0, 0.0, 3.0), clip(0.2, -0.2, 0.0)];
+        let f = frustum();
+        let polygon = f.clip_triangle(&verts);
+        // one corner is cut off, leaving a quad
+        assert!(polygon.len() == 4);
+        for corner in polygon.iter() {
+            // w is the distance from the eye divided by the eye distance
+            assert!(corner.position.w * 2.0 >= 0.5 - 1e-4);
+            let bar = corner.bar;
+            assert!((bar.x + bar.y + bar.z - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn triangle_crossing_side_plane_stays_on_screen() {
+        let verts = [clip(-3.0, 0.0, 0.0), clip(0.5, -0.5, 0.0), clip(0.5, 0.5, 0.0)];
+        let polygon = frustum().clip_triangle(&verts);
+        assert!(polygon.len() == 4);
+        for corner in polygon.iter() {
+            assert!(corner.position.to_vector().x >= -1e-4);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::vec::Vec;
+
+use geometry::Vertex3;
+use material::Material;
+use model::Model;
+
+/// Writes the model as OBJ: positions, texture coordinates, normals and
+/// faces, with a `g` for every mesh and `usemtl` and `s` wherever the
+/// material or smoothing group changes. `library` is named in an `mtllib`
+/// statement when given.
+///
+/// Faces without a material that follow faces with one can't be expressed
+/// in OBJ and end up with the previous material when read back.
+pub fn write_obj<W: Write>(model: &Model, writer: &mut W, library: Option<&str>) -> io::Result<()> {
+    writeln!(writer, "# written by pocket-renderer")?;
+    if let Some(library) = library {
+        writeln!(writer, "mtllib {}", library)?;
+    }
+    for v in model.verts.iter() {
+        writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?;
+    }
+    for vt in model.textures.iter() {
+        writeln!(writer, "vt {} {}", vt.x, vt.y)?;
+    }
+    for vn in model.normals.iter() {
+        writeln!(writer, "vn {} {} {}", vn.x, vn.y, vn.z)?;
+    }
+
+    let mut material = None;
+    let mut smoothing_group = Some(0);
+    for (f, face) in model.faces.iter().enumerate() {
+        if let Some(mesh) = model.meshes.iter().find(|mesh| mesh.faces.start == f && !mesh.faces.is_empty()) {
+            writeln!(writer, "g {}", mesh.name)?;
+        }
+        if let Some(index) = face.material {
+            if material != Some(index) {
+                material = Some(index);
+                writeln!(writer, "usemtl {}", model.materials[index].name)?;
+            }
+        }
+        if face.smoothing_group != smoothing_group {
+            smoothing_group = face.smoothing_group;
+            match smoothing_group {
+                Some(group) => writeln!(writer, "s {}", group)?,
+                None => writeln!(writer, "s off")?,
+            }
+        }
+        write!(writer, "f")?;
+        for i in 0..3 {
+            // OBJ indices start at 1
+            let v = face.get_vertex(i) + 1;
+            match (face.get_texture(i), face.get_normal(i)) {
+                (None, None) => write!(writer, " {}", v)?,
+                (Some(vt), None) => write!(writer, " {}/{}", v, vt + 1)?,
+                (None, Some(vn)) => write!(writer, " {}//{}", v, vn + 1)?,
+                (Some(vt), Some(vn)) => write!(writer, " {}/{}/{}", v, vt + 1, vn + 1)?,
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Writes the constants of each material as an MTL library. Texture maps
+/// are not written.
+pub fn write_mtl<W: Write>(materials: &[Material], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "# written by pocket-renderer")?;
+    for material in materials.iter() {
+        let color = |c: Vertex3<f32>| format!("{} {} {}", c.x, c.y, c.z);
+        writeln!(writer, "\nnewmtl {}", material.name)?;
+        writeln!(writer, "Ka {}", color(material.ambient))?;
+        writeln!(writer, "Kd {}", color(material.diffuse))?;
+        writeln!(writer, "Ks {}", color(material.specular))?;
+        writeln!(writer, "Ns {}", material.shininess)?;
+        writeln!(writer, "d {}", material.dissolve)?;
+    }
+    Ok(())
+}
+
+/// Writes the model as binary little-endian PLY.
+///
+/// PLY keeps one set of attributes per vertex, so every distinct
+/// combination of position, texture coordinates and normal becomes a
+/// vertex. Texture coordinates and normals are written when any face has
+/// them, along with the vertex colors if the model has any. Faces carry a
+/// `material_index` into the materials named by `comment material` lines,
+/// or -1 without a material.
+pub fn write_ply<W: Write>(model: &Model, writer: &mut W) -> io::Result<()> {
+    let has_textures = model.faces.iter().any(|face| face.textures.is_some());
+    let has_normals = model.faces.iter().any(|face| face.normals.is_some());
+    let has_colors = !model.colors.is_empty();
+
+    // the face and corner each vertex was first seen at
+    let mut vertices: Vec<(usize, usize)> = Vec::new();
+    let mut indices: HashMap<(u32, Option<u32>, Option<u32>), u32> = HashMap::new();
+    let mut faces: Vec<[u32; 3]> = Vec::with_capacity(model.faces.len());
+    for (f, face) in model.faces.iter().enumerate() {
+        let mut corners = [0; 3];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let key = (face.get_vertex(i), face.get_texture(i), face.get_normal(i));
+            *corner = *indices.entry(key).or_insert_with(|| {
+                vertices.push((f, i));
+                vertices.len() as u32 - 1
+            });
+        }
+        faces.push(corners);
+    }
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "comment written by pocket-renderer")?;
+    for material in model.materials.iter() {
+        writeln!(writer, "comment material {}", material.name)?;
+    }
+    writeln!(writer, "element vertex {}", vertices.len())?;
+    writeln!(writer, "property float x\nproperty float y\nproperty float z")?;
+    if has_normals {
+        writeln!(writer, "property float nx\nproperty float ny\nproperty float nz")?;
+    }
+    if has_textures {
+        writeln!(writer, "property float u\nproperty float v")?;
+    }
+    if has_colors {
+        writeln!(writer, "property uchar red\nproperty uchar green\nproperty uchar blue")?;
+    }
+    writeln!(writer, "element face {}", faces.len())?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "property int material_index")?;
+    writeln!(writer, "end_header")?;
+
+    for &(f, i) in vertices.iter() {
+        let face = &model.faces[f];
+        let v = model.vertex(face, i);
+        write_floats(writer, &[v.x, v.y, v.z])?;
+        if has_normals {
+            let n = model.vertex_normal(face, i);
+            write_floats(writer, &[n.x, n.y, n.z])?;
+        }
+        if has_textures {
+            let uv = model.vertex_uv(face, i);
+            write_floats(writer, &[uv.x, uv.y])?;
+        }
+        if has_colors {
+            let color = model.vertex_color(face, i).unwrap_or_else(|| Vertex3::init(1.0, 1.0, 1.0));
+            let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            writer.write_all(&[channel(color.x), channel(color.y), channel(color.z)])?;
+        }
+    }
+    for (face, corners) in model.faces.iter().zip(faces.iter()) {
+        writer.write_all(&[3])?;
+        for corner in corners.iter() {
+            writer.write_all(&corner.to_le_bytes())?;
+        }
+        let material = face.material.map(|m| m as i32).unwrap_or(-1);
+        writer.write_all(&material.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_floats<W: Write>(writer: &mut W, values: &[f32]) -> io::Result<()> {
+    for value in values.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use normals::NormalSettings;
+    use ply;
+
+    const OBJ: &str = "mtllib scene.mtl\n\
+        v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\n\
+        vt 0 0\nvt 1 0\nvt 1 1\n\
+        vn 0 0 1\n\
+        g floor\nusemtl stone\nf 1/1/1 2/2/1 3/3/1\n\
+        s off\nf 1 3 4\n\
+        g wall\nusemtl wood\ns 2\nf 1/1 5/2 2/3\n";
+
+    fn write(model: &Model) -> String {
+        let mut out = Vec::new();
+        write_obj(model, &mut out, Some("scene.mtl")).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn obj_round_trips() {
+        let model = Model::from_reader(OBJ.as_bytes()).unwrap();
+        let written = write(&model);
+        assert!(written.contains("mtllib scene.mtl\n"));
+        assert!(written.contains("g floor\nusemtl stone\nf 1/1/1 2/2/1 3/3/1\ns off\nf 1 3 4\n"));
+
+        let read = Model::from_reader(written.as_bytes()).unwrap();
+        assert!(read.verts == model.verts && read.textures == model.textures && read.normals == model.normals);
+        assert!(read.meshes == model.meshes);
+        for (a, b) in read.faces.iter().zip(model.faces.iter()) {
+            assert!(a.vertexes == b.vertexes && a.textures == b.textures && a.normals == b.normals);
+            assert!(a.material == b.material && a.smoothing_group == b.smoothing_group);
+        }
+        // writing what was read back changes nothing
+        assert!(write(&read) == written);
+    }
+
+    #[test]
+    fn mtl_round_trips() {
+        let mut material = Material::new("stone");
+        material.diffuse = Vertex3::init(0.5, 0.25, 1.0);
+        material.shininess = 12.0;
+        material.dissolve = 0.5;
+        let mut out = Vec::new();
+        write_mtl(&[material], &mut out).unwrap();
+        let read = Material::parse_library(&out[..], ::std::path::Path::new("")).unwrap();
+        assert!(read.len() == 1 && read[0].name == "stone");
+        assert!(read[0].diffuse == Vertex3::init(0.5, 0.25, 1.0));
+        assert!(read[0].shininess == 12.0 && read[0].dissolve == 0.5);
+    }
+
+    #[test]
+    fn ply_round_trips() {
+        let mut model = Model::from_reader(OBJ.as_bytes()).unwrap();
+        model.colors = vec![Vertex3::init(1.0, 0.0, 0.0); model.verts.len()];
+        let mut out = Vec::new();
+        write_ply(&model, &mut out).unwrap();
+
+        let read = ply::from_slice(&out, NormalSettings::new()).unwrap();
+        assert!(read.faces.len() == model.faces.len());
+        assert!(read.materials.iter().map(|m| m.name.as_str()).eq(["stone", "wood"].iter().cloned()));
+        for (a, b) in read.faces.iter().zip(model.faces.iter()) {
+            assert!(a.material == b.material);
+            for i in 0..3 {
+                assert!(read.vertex(a, i) == model.vertex(b, i));
+                assert!(read.vertex_uv(a, i) == model.vertex_uv(b, i));
+                assert!(read.vertex_normal(a, i) == model.vertex_normal(b, i));
+                assert!(read.vertex_color(a, i) == Some(Vertex3::init(1.0, 0.0, 0.0)));
+            }
+        }
+    }
+}
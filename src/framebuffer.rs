@@ -0,0 +1,232 @@
+use image::{Rgb, RgbImage};
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::WindowCanvas;
+use std::f32;
+use std::vec::Vec;
+
+/// Something the rasterizer can draw into: a color attachment plus a depth
+/// attachment of the same dimensions.
+pub trait RenderTarget {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    fn pixel(&self, x: u32, y: u32) -> Color;
+    fn depth(&self, x: u32, y: u32) -> f32;
+    fn set_depth(&mut self, x: u32, y: u32, z: f32);
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color);
+    fn clear(&mut self, color: Color);
+}
+
+/// In-memory render target, usable without a window or video subsystem.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pub pixels: Vec<Color>,
+    pub zbuffer: Vec<f32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        let size = (width * height) as usize;
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::RGB(0, 0, 0); size],
+            zbuffer: vec![f32::NEG_INFINITY; size],
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[self.index(x, y)]
+    }
+
+    /// Copies the color attachment into an image. Rows are flipped so that
+    /// the framebuffer origin ends up in the bottom-left corner.
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let pixel = self.get_pixel(x, self.height - 1 - y);
+            Rgb([pixel.r, pixel.g, pixel.b])
+        })
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (x + y * self.width) as usize
+    }
+}
+
+impl RenderTarget for Framebuffer {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Color {
+        self.get_pixel(x, y)
+    }
+
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.zbuffer[self.index(x, y)]
+    }
+
+    fn set_depth(&mut self, x: u32, y: u32, z: f32) {
+        let idx = self.index(x, y);
+        self.zbuffer[idx] = z;
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        let idx = self.index(x, y);
+        self.pixels[idx] = color;
+    }
+
+    fn clear(&mut self, color: Color) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = color;
+        }
+        for z in self.zbuffer.iter_mut() {
+            *z = f32::NEG_INFINITY;
+        }
+    }
+}
+
+/// Adapter that draws straight onto an SDL window canvas, keeping its own
+/// depth buffer alongside it, and a copy of the colors so they can be
+/// blended with.
+pub struct CanvasTarget {
+    canvas: WindowCanvas,
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    zbuffer: Vec<f32>,
+}
+
+impl CanvasTarget {
+    pub fn new(canvas: WindowCanvas) -> CanvasTarget {
+        let (width, height) = canvas.output_size().unwrap();
+        CanvasTarget {
+            canvas,
+            width,
+            height,
+            pixels: vec![Color::RGB(0, 0, 0); (width * height) as usize],
+            zbuffer: vec![f32::NEG_INFINITY; (width * height) as usize],
+        }
+    }
+
+    pub fn present(&mut self) {
+        self.canvas.present();
+    }
+}
+
+impl RenderTarget for CanvasTarget {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[(x + y * self.width) as usize]
+    }
+
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.zbuffer[(x + y * self.width) as usize]
+    }
+
+    fn set_depth(&mut self, x: u32, y: u32, z: f32) {
+        self.zbuffer[(x + y * self.width) as usize] = z;
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(x + y * self.width) as usize] = color;
+        self.canvas.set_draw_color(color);
+        self.canvas.draw_point(Point::new(x as i32, y as i32)).ok();
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.canvas.set_draw_color(color);
+        self.canvas.clear();
+        for pixel in self.pixels.iter_mut() {
+            *pixel = color;
+        }
+        for z in self.zbuffer.iter_mut() {
+            *z = f32::NEG_INFINITY;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::{Derivatives, Vertex3};
+    use renderer::{self, Shader};
+
+    struct FlatShader {
+        color: Color,
+    }
+
+    impl Shader for FlatShader {
+        fn fragment(&self, _bar: Vertex3<f32>, _derivatives: Derivatives, pixel: &mut Color) -> bool {
+            *pixel = self.color;
+            true
+        }
+    }
+
+    fn flat(r: u8, g: u8, b: u8) -> FlatShader {
+        FlatShader {
+            color: Color::RGB(r, g, b),
+        }
+    }
+
+    #[test]
+    fn framebuffer_starts_cleared() {
+        let fb = Framebuffer::new(4, 3);
+        assert!(fb.get_pixel(3, 2) == Color::RGB(0, 0, 0));
+        assert!(fb.depth(3, 2) == f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn triangle_renders_into_framebuffer() {
+        let mut fb = Framebuffer::new(16, 16);
+        let verts = [
+            Vertex3::init(0.0, 0.0, 1.0),
+            Vertex3::init(15.0, 0.0, 1.0),
+            Vertex3::init(0.0, 15.0, 1.0),
+        ];
+        let count = renderer::triangle(&verts, flat(255, 0, 0), &mut fb);
+        assert!(count > 0);
+        assert!(fb.get_pixel(2, 2) == Color::RGB(255, 0, 0));
+        assert!(fb.get_pixel(15, 15) == Color::RGB(0, 0, 0));
+        assert!(fb.depth(2, 2) == 1.0);
+    }
+
+    #[test]
+    fn to_image_flips_rows() {
+        let mut fb = Framebuffer::new(2, 2);
+        fb.set_pixel(0, 0, Color::RGB(255, 0, 0));
+        let image = fb.to_image();
+        assert!(*image.get_pixel(0, 1) == Rgb([255, 0, 0]));
+        assert!(*image.get_pixel(0, 0) == Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn triangle_respects_depth_buffer() {
+        let mut fb = Framebuffer::new(16, 16);
+        let near = [
+            Vertex3::init(0.0, 0.0, 2.0),
+            Vertex3::init(15.0, 0.0, 2.0),
+            Vertex3::init(0.0, 15.0, 2.0),
+        ];
+        let far = [
+            Vertex3::init(0.0, 0.0, 1.0),
+            Vertex3::init(15.0, 0.0, 1.0),
+            Vertex3::init(0.0, 15.0, 1.0),
+        ];
+        renderer::triangle(&near, flat(0, 255, 0), &mut fb);
+        renderer::triangle(&far, flat(0, 0, 255), &mut fb);
+        assert!(fb.get_pixel(2, 2) == Color::RGB(0, 255, 0));
+    }
+}
@@ -17,6 +17,14 @@ pub struct Vertex2<T> {
     pub y: T,
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct Vertex4<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
+    pub w: T,
+}
+
 #[derive(Debug, Clone)]
 pub struct Matrix4 {
     pub m: [[f32; 4]; 4]
@@ -50,8 +58,62 @@ impl Matrix4 {
         matrix
     }
 
-    pub fn invert_transpose(&self) -> Matrix4 {
-        Matrix4::identity()
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = Matrix4::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                result.set(j, i, self.get(i, j));
+            }
+        }
+        result
+    }
+
+    pub fn determinant(&self) -> f32 {
+        (0..4).map(|j| self.get(0, j) * self.cofactor(0, j)).sum()
+    }
+
+    /// General inverse computed from the adjugate. Returns `None` when the
+    /// matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let det = self.determinant();
+        // an absolute threshold would reject small but well-conditioned
+        // transforms, such as a uniform scale of 0.01 with det 1e-6
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let mut result = Matrix4::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                // the adjugate is the transpose of the cofactor matrix
+                result.set(j, i, self.cofactor(i, j) / det);
+            }
+        }
+        Some(result)
+    }
+
+    /// Inverse-transpose, used to carry normals through a model transform.
+    pub fn invert_transpose(&self) -> Option<Matrix4> {
+        self.inverse().map(|inverse| inverse.transpose())
+    }
+
+    fn cofactor(&self, row: usize, col: usize) -> f32 {
+        let sign = [1.0, -1.0][(row + col) % 2];
+        sign * self.minor(row, col)
+    }
+
+    // determinant of the 3x3 matrix left after removing `row` and `col`
+    fn minor(&self, row: usize, col: usize) -> f32 {
+        let mut m = [[0.0; 3]; 3];
+        let rows = (0..4).filter(|&i| i != row);
+        for (mi, i) in rows.enumerate() {
+            let cols = (0..4).filter(|&j| j != col);
+            for (mj, j) in cols.enumerate() {
+                m[mi][mj] = self.get(i, j);
+            }
+        }
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
     }
 
     pub fn to_vector(&self) -> Vertex3<f32> {
@@ -67,9 +129,8 @@ impl PartialEq for Matrix4 {
     fn eq(&self, other: &Self) -> bool {
         for x in 0..4 {
             for y in 0..4 {
-                match self.get(x, y) == other.get(x, y) {
-                    true => return true,
-                    false => return false,
+                if self.get(x, y) != other.get(x, y) {
+                    return false;
                 }
             }
         }
@@ -80,16 +141,33 @@ impl PartialEq for Matrix4 {
 impl Mul<Matrix4> for Matrix4 {
     type Output = Self;
 
-    fn mul(mut self, rhs: Matrix4) -> Self {
+    fn mul(self, rhs: Matrix4) -> Self {
+        let mut result = Matrix4::new();
         for i in 0..4 {
             for j in 0..4 {
                 for k in 0..4 {
-                    let value = self.get(i, j) + (self.get(i, k) * rhs.get(k, j));
-                    self.set(i, j, value);
+                    let value = result.get(i, j) + (self.get(i, k) * rhs.get(k, j));
+                    result.set(i, j, value);
                 }
             }
         }
-        self
+        result
+    }
+}
+
+impl Mul<Vertex4<f32>> for Matrix4 {
+    type Output = Vertex4<f32>;
+
+    fn mul(self, rhs: Vertex4<f32>) -> Vertex4<f32> {
+        let row = |i: usize| {
+            self.get(i, 0) * rhs.x + self.get(i, 1) * rhs.y + self.get(i, 2) * rhs.z + self.get(i, 3) * rhs.w
+        };
+        Vertex4::<f32> {
+            x: row(0),
+            y: row(1),
+            z: row(2),
+            w: row(3),
+        }
     }
 }
 
@@ -143,6 +221,10 @@ impl Vertex3<f32> {
         }
     }
 
+    pub fn to_homogeneous(&self) -> Vertex4<f32> {
+        Vertex4::init(self.x, self.y, self.z, 1.0)
+    }
+
     pub fn to_matrix(&self) -> Matrix4 {
         let mut m = [[0.0; 4]; 4];
         m[0][0] = self.x;
@@ -155,6 +237,59 @@ impl Vertex3<f32> {
     }
 }
 
+impl Vertex4<f32> {
+    pub fn init(x: f32, y: f32, z: f32, w: f32) -> Vertex4<f32> {
+        Vertex4 { x, y, z, w }
+    }
+
+    /// Perspective divide back to three dimensions.
+    pub fn to_vector(&self) -> Vertex3<f32> {
+        Vertex3::<f32> {
+            x: self.x / self.w,
+            y: self.y / self.w,
+            z: self.z / self.w,
+        }
+    }
+}
+
+impl Add for Vertex4<f32> {
+    type Output = Vertex4<f32>;
+
+    fn add(self, other: Vertex4<f32>) -> Vertex4<f32> {
+        Vertex4::init(self.x + other.x, self.y + other.y, self.z + other.z, self.w + other.w)
+    }
+}
+
+impl Sub for Vertex4<f32> {
+    type Output = Vertex4<f32>;
+
+    fn sub(self, rhs: Vertex4<f32>) -> Vertex4<f32> {
+        Vertex4::init(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z, self.w - rhs.w)
+    }
+}
+
+impl Mul<f32> for Vertex4<f32> {
+    type Output = Vertex4<f32>;
+
+    fn mul(self, rhs: f32) -> Vertex4<f32> {
+        Vertex4::init(self.x * rhs, self.y * rhs, self.z * rhs, self.w * rhs)
+    }
+}
+
+impl Mul<Vertex4<f32>> for Vertex4<f32> {
+    type Output = f32;
+
+    fn mul(self, rhs: Vertex4<f32>) -> f32 {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z + self.w * rhs.w
+    }
+}
+
+impl PartialEq for Vertex4<f32> {
+    fn eq(&self, other: &Self) -> bool {
+        self.x == other.x && self.y == other.y && self.z == other.z && self.w == other.w
+    }
+}
+
 impl<T: Add<Output = T>> Add for Vertex2<T> {
     type Output = Vertex2<T>;
 
@@ -361,6 +496,34 @@ impl PartialEq for Vertex3<f32> {
     }
 }
 
+/// How a value interpolated across a triangle changes from one pixel to the
+/// next along the x and y axes of the screen.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Derivatives {
+    pub dx: Vertex3<f32>,
+    pub dy: Vertex3<f32>,
+}
+
+impl Derivatives {
+    /// A value that doesn't change, which samples textures at full detail.
+    pub fn zero() -> Derivatives {
+        Derivatives {
+            dx: Vertex3::new(),
+            dy: Vertex3::new(),
+        }
+    }
+
+    /// Taking these as derivatives of barycentric coordinates, the
+    /// derivatives of the value interpolated from `values` at the corners.
+    pub fn interpolate(&self, values: &[Vertex3<f32>; 3]) -> Derivatives {
+        let apply = |d: Vertex3<f32>| (values[0] * d.x) + (values[1] * d.y) + (values[2] * d.z);
+        Derivatives {
+            dx: apply(self.dx),
+            dy: apply(self.dy),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -449,15 +612,103 @@ mod tests {
         assert!(actual == expected);
     }
 
+    #[test]
+    fn matrix_vector_multiplication() {
+        let mut a = Matrix4::identity();
+        a.set(0, 3, 2.0);
+        a.set(3, 2, -0.5);
+        let actual = a * Vertex3::init(1.0, 2.0, 4.0).to_homogeneous();
+        assert!(actual == Vertex4::init(3.0, 2.0, 4.0, -1.0));
+        assert!(actual.to_vector() == Vertex3::init(-3.0, -2.0, -4.0));
+    }
+
+    fn assert_matrix_near(actual: &Matrix4, expected: &Matrix4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                let diff = (actual.get(i, j) - expected.get(i, j)).abs();
+                assert!(diff < 1e-5, "{:?} != {:?}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn matrix_not_equal() {
+        let a = Matrix4::identity();
+        let mut b = Matrix4::identity();
+        b.set(3, 2, 5.0);
+        assert!(a != b);
+    }
+
+    #[test]
+    fn matrix_transpose() {
+        let a = Matrix4 {
+            m: [[1.0, 2.0, 3.0, 4.0], [5.0, 6.0, 7.0, 8.0], [9.0, 10.0, 11.0, 12.0], [13.0, 14.0, 15.0, 16.0]],
+        };
+        let expected = Matrix4 {
+            m: [[1.0, 5.0, 9.0, 13.0], [2.0, 6.0, 10.0, 14.0], [3.0, 7.0, 11.0, 15.0], [4.0, 8.0, 12.0, 16.0]],
+        };
+        assert!(a.transpose() == expected);
+    }
+
+    #[test]
+    fn matrix_determinant() {
+        let a = Matrix4 {
+            m: [[2.0, 0.0, 0.0, 1.0], [0.0, 3.0, 0.0, 0.0], [0.0, 0.0, 4.0, 0.0], [1.0, 0.0, 0.0, 1.0]],
+        };
+        assert!((a.determinant() - 12.0).abs() < 1e-5);
+        assert!(Matrix4::identity().determinant() == 1.0);
+    }
+
+    #[test]
+    fn matrix_inverse() {
+        let a = Matrix4 {
+            m: [[4.0, 7.0, 2.0, 3.0], [0.0, 5.0, 0.0, 1.0], [1.0, 0.0, 3.0, 0.0], [2.0, 1.0, 0.0, 6.0]],
+        };
+        let inverse = a.inverse().unwrap();
+        assert_matrix_near(&(a.clone() * inverse.clone()), &Matrix4::identity());
+        assert_matrix_near(&(inverse * a), &Matrix4::identity());
+    }
+
+    #[test]
+    fn small_scale_has_an_inverse() {
+        let mut a = Matrix4::identity();
+        for i in 0..3 {
+            a.set(i, i, 0.01);
+        }
+        let inverse = a.inverse().unwrap();
+        assert!((inverse.get(0, 0) - 100.0).abs() < 1e-3);
+        assert_matrix_near(&(a * inverse), &Matrix4::identity());
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        let a = Matrix4 {
+            m: [[1.0, 2.0, 3.0, 4.0], [2.0, 4.0, 6.0, 8.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]],
+        };
+        assert!(a.inverse().is_none());
+        assert!(a.invert_transpose().is_none());
+    }
+
     #[test]
     fn matrix_invert_transpose() {
         let a = Matrix4 {
             m: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]],
         };
-        let actual = a.invert_transpose();
+        let actual = a.invert_transpose().unwrap();
+        let expected = Matrix4::identity();
+        assert!(actual == expected);
+    }
+
+    #[test]
+    fn matrix_invert_transpose_of_non_uniform_scale() {
+        let mut a = Matrix4::identity();
+        a.set(0, 0, 2.0);
+        a.set(1, 1, 4.0);
+        a.set(0, 3, 7.0);
+        let actual = a.invert_transpose().unwrap();
         let expected = Matrix4 {
-            m: [[0.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 0.0], [0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]],
+            m: [[0.5, 0.0, 0.0, 0.0], [0.0, 0.25, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [-3.5, 0.0, 0.0, 1.0]],
         };
-        assert!(actual == expected);
+        assert_matrix_near(&actual, &expected);
     }
 }
@@ -0,0 +1,491 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use gltf::buffer;
+use gltf::image::Source;
+use gltf::mesh::Mode;
+use gltf::texture::{MagFilter, WrappingMode};
+use gltf::{Gltf, Node, Primitive};
+use image;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+use geometry::{Matrix4, Vertex3, Vertex4};
+use material::Material;
+use model::{Face, Mesh, Model, ModelError};
+use tangents;
+use texture::{Filter, Texture, Wrap};
+
+/// Loads a `.gltf` file, with its buffers and images next to it or embedded
+/// as data URIs, or a binary `.glb` file.
+pub fn load(path: &Path) -> Result<Model, ModelError> {
+    let data = fs::read(path).map_err(|error| ModelError::Open {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    from_slice(&data, path)
+}
+
+/// Builds a model from glTF or GLB data. External files are looked up
+/// relative to `path`, which is also what errors refer to.
+///
+/// Every primitive of every mesh in the default scene becomes a `Mesh`,
+/// with the node transforms applied. Base color factors and textures map
+/// to the diffuse color and map of a `Material`, normal textures to its
+/// bump map. Primitives without normals are drawn flat and ones without
+/// tangents get them generated, as the specification asks.
+pub fn from_slice(data: &[u8], path: &Path) -> Result<Model, ModelError> {
+    let gltf = Gltf::from_slice(data).map_err(|error| invalid(path, error))?;
+    let buffers = load_buffers(&gltf, path)?;
+    let mut importer = Importer {
+        path,
+        buffers: &buffers,
+        images: HashMap::new(),
+        model: Model::empty(),
+    };
+
+    for material in gltf.document.materials() {
+        let material = importer.material(&material)?;
+        importer.model.materials.push(material);
+    }
+    let scene = gltf.document.default_scene().or_else(|| gltf.document.scenes().next());
+    match scene {
+        Some(scene) => {
+            for node in scene.nodes() {
+                importer.node(&node, &Matrix4::identity())?;
+            }
+        }
+        // without a scene the meshes are all placed at the origin
+        None => {
+            for mesh in gltf.document.meshes() {
+                let name = mesh.name().map(str::to_string).unwrap_or_else(|| format!("mesh{}", mesh.index()));
+                for primitive in mesh.primitives() {
+                    importer.primitive(&primitive, &name, &Matrix4::identity())?;
+                }
+            }
+        }
+    }
+
+    let mut model = importer.model;
+    tangents::generate(&mut model);
+    Ok(model)
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: &'a [Vec<u8>],
+    images: HashMap<usize, Texture>,
+    model: Model,
+}
+
+impl<'a> Importer<'a> {
+    fn node(&mut self, node: &Node, parent: &Matrix4) -> Result<(), ModelError> {
+        // glTF matrices are stored column by column
+        let local = Matrix4 {
+            m: node.transform().matrix(),
+        }
+        .transpose();
+        let transform = parent.clone() * local;
+        if let Some(mesh) = node.mesh() {
+            let name = mesh
+                .name()
+                .or_else(|| node.name())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("mesh{}", mesh.index()));
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, &name, &transform)?;
+            }
+        }
+        for child in node.children() {
+            self.node(&child, &transform)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(&mut self, primitive: &Primitive, name: &str, transform: &Matrix4) -> Result<(), ModelError> {
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+        let positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(positions) => positions.collect(),
+            None => return Ok(()),
+        };
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= positions.len()) {
+            return Err(invalid(self.path, format!("index {} is out of range", index)));
+        }
+        // the other attributes are looked up with the position indices
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| normals.collect());
+        let uvs: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect());
+        let tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(|tangents| tangents.collect());
+        let counts = [
+            ("NORMAL", normals.as_ref().map(Vec::len)),
+            ("TEXCOORD_0", uvs.as_ref().map(Vec::len)),
+            ("TANGENT", tangents.as_ref().map(Vec::len)),
+        ];
+        for &(attribute, count) in counts.iter() {
+            match count {
+                Some(count) if count != positions.len() => {
+                    let message = format!("{} has {} elements but POSITION has {}", attribute, count, positions.len());
+                    return Err(invalid(self.path, message));
+                }
+                _ => {}
+            }
+        }
+        let triangles = match triangles(primitive.mode(), &indices) {
+            Some(triangles) => triangles,
+            // points and lines have no surface to draw
+            None => return Ok(()),
+        };
+
+        let normal_transform = transform.invert_transpose().unwrap_or_else(Matrix4::identity);
+        let model = &mut self.model;
+        let base = model.verts.len() as u32;
+        model.verts.extend(positions.iter().map(|p| {
+            let v = transform.clone() * Vertex3::init(p[0], p[1], p[2]).to_homogeneous();
+            v.to_vector()
+        }));
+        let direction = |matrix: &Matrix4, d: [f32; 3]| {
+            let v = matrix.clone() * Vertex4::init(d[0], d[1], d[2], 0.0);
+            Vertex3::init(v.x, v.y, v.z).normalize()
+        };
+        let normals_base = model.normals.len() as u32;
+        let has_normals = match normals {
+            Some(normals) => {
+                model.normals.extend(normals.into_iter().map(|n| direction(&normal_transform, n)));
+                true
+            }
+            None => false,
+        };
+        let textures_base = model.textures.len() as u32;
+        let has_textures = match uvs {
+            // glTF puts the origin of texture space at the top left
+            Some(uvs) => {
+                model.textures.extend(uvs.into_iter().map(|uv| Vertex3::init(uv[0], 1.0 - uv[1], 0.0)));
+                true
+            }
+            None => false,
+        };
+        let tangents_base = model.tangents.len() as u32;
+        let has_tangents = match tangents {
+            Some(tangents) if has_textures => {
+                model.tangents.extend(tangents.into_iter().map(|t| {
+                    let d = direction(transform, [t[0], t[1], t[2]]);
+                    Vertex4::init(d.x, d.y, d.z, t[3])
+                }));
+                true
+            }
+            _ => false,
+        };
+
+        let start = model.faces.len();
+        let material = primitive.material().index();
+        for triangle in triangles {
+            let offset = |base: u32| [triangle[0] + base, triangle[1] + base, triangle[2] + base];
+            model.faces.push(Face {
+                vertexes: offset(base),
+                textures: if has_textures { Some(offset(textures_base)) } else { None },
+                normals: if has_normals { Some(offset(normals_base)) } else { None },
+                tangents: if has_tangents { Some(offset(tangents_base)) } else { None },
+                material,
+                smoothing_group: None,
+            });
+        }
+        model.meshes.push(Mesh {
+            name: name.to_string(),
+            faces: start..model.faces.len(),
+            material,
+        });
+        Ok(())
+    }
+
+    fn material(&mut self, material: &gltf::Material) -> Result<Material, ModelError> {
+        let name = match material.name() {
+            Some(name) => name.to_string(),
+            None => format!("material{}", material.index().unwrap_or(0)),
+        };
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let mut result = Material::new(&name);
+        result.diffuse = Vertex3::init(r, g, b);
+        result.dissolve = a;
+        if let Some(info) = pbr.base_color_texture() {
+            result.diffuse_map = Some(self.texture(&info.texture())?);
+        }
+        if let Some(normal) = material.normal_texture() {
+            result.bump_map = Some(self.texture(&normal.texture())?);
+        }
+        Ok(result)
+    }
+
+    // The texture's image with its sampler's wrap modes and magnification
+    // filter.
+    fn texture(&mut self, texture: &gltf::Texture) -> Result<Texture, ModelError> {
+        let mut result = self.image(&texture.source())?;
+        let sampler = texture.sampler();
+        result.sampler.wrap_u = wrap(sampler.wrap_s());
+        result.sampler.wrap_v = wrap(sampler.wrap_t());
+        if sampler.mag_filter() == Some(MagFilter::Nearest) {
+            result.sampler.filter = Filter::Nearest;
+        }
+        Ok(result)
+    }
+
+    fn image(&mut self, image: &gltf::Image) -> Result<Texture, ModelError> {
+        if let Some(loaded) = self.images.get(&image.index()) {
+            return Ok(loaded.clone());
+        }
+        let (bytes, source) = match image.source() {
+            Source::View { view, .. } => {
+                let buffer = &self.buffers[view.buffer().index()];
+                let end = view.offset() + view.length();
+                if end > buffer.len() {
+                    return Err(invalid(self.path, "image data runs past the end of its buffer"));
+                }
+                (buffer[view.offset()..end].to_vec(), self.path.to_path_buf())
+            }
+            Source::Uri { uri, .. } => read_uri(uri, self.path)?,
+        };
+        let decoded = image::load_from_memory(&bytes)
+            .map_err(|error| ModelError::Texture { path: source, error })?
+            .flipv();
+        let decoded = Texture::new(decoded);
+        self.images.insert(image.index(), decoded.clone());
+        Ok(decoded)
+    }
+}
+
+fn wrap(mode: WrappingMode) -> Wrap {
+    match mode {
+        WrappingMode::Repeat => Wrap::Repeat,
+        WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+    }
+}
+
+// Splits the indices into triangles, or None for points and lines.
+fn triangles(mode: Mode, indices: &[u32]) -> Option<Vec<[u32; 3]>> {
+    let triangles = match mode {
+        Mode::Triangles => indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+        // every other triangle of a strip is flipped to keep the winding
+        Mode::TriangleStrip => (2..indices.len())
+            .map(|i| match i % 2 {
+                0 => [indices[i - 2], indices[i - 1], indices[i]],
+                _ => [indices[i - 1], indices[i - 2], indices[i]],
+            })
+            .collect(),
+        Mode::TriangleFan => (2..indices.len())
+            .map(|i| [indices[0], indices[i - 1], indices[i]])
+            .collect(),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => return None,
+    };
+    Some(triangles)
+}
+
+fn load_buffers(gltf: &Gltf, path: &Path) -> Result<Vec<Vec<u8>>, ModelError> {
+    let mut buffers = Vec::new();
+    for buffer in gltf.document.buffers() {
+        let mut data = match buffer.source() {
+            buffer::Source::Bin => match gltf.blob {
+                Some(ref blob) => blob.clone(),
+                None => return Err(invalid(path, "missing binary chunk")),
+            },
+            buffer::Source::Uri(uri) => read_uri(uri, path)?.0,
+        };
+        if data.len() < buffer.length() {
+            return Err(invalid(path, format!("buffer {} is too short", buffer.index())));
+        }
+        // the binary chunk may be padded
+        data.truncate(buffer.length());
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+// Reads a data URI or a file relative to the glTF file, returning the bytes
+// and the path to blame if they turn out to be broken.
+fn read_uri(uri: &str, path: &Path) -> Result<(Vec<u8>, PathBuf), ModelError> {
+    if uri.starts_with("data:") {
+        let data = match uri.find(";base64,") {
+            Some(start) => STANDARD.decode(&uri[start + 8..]).ok(),
+            None => None,
+        };
+        return match data {
+            Some(data) => Ok((data, path.to_path_buf())),
+            None => Err(invalid(path, "only base64 data URIs are supported")),
+        };
+    }
+    let file = path.parent().unwrap_or_else(|| Path::new("")).join(percent_decode(uri));
+    match fs::read(&file) {
+        Ok(data) => Ok((data, file)),
+        Err(error) => Err(ModelError::Open { path: file, error }),
+    }
+}
+
+// URIs escape spaces and other special characters in file names.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn invalid<E: ToString>(path: &Path, error: E) -> ModelError {
+    ModelError::Gltf {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle with normals, texture coordinates and 16-bit indices:
+    // 36 bytes of positions, 36 of normals, 24 of UVs and 6 of indices.
+    fn triangle_buffer() -> Vec<u8> {
+        let floats: [f32; 24] = [
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, // positions
+            0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, // normals
+            0.0, 0.0, 1.0, 0.0, 0.0, 1.0, // texture coordinates
+        ];
+        let mut data: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes().to_vec()).collect();
+        for index in [0u16, 1, 2].iter() {
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+        data
+    }
+
+    fn triangle_json(buffer: &str) -> String {
+        format!(
+            r#"{{
+  "asset": {{ "version": "2.0" }},
+  "scene": 0,
+  "scenes": [{{ "nodes": [0] }}],
+  "nodes": [{{ "mesh": 0, "translation": [0, 0, -2] }}],
+  "meshes": [{{ "name": "tri", "primitives": [{{
+    "attributes": {{ "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 }},
+    "indices": 3,
+    "material": 0
+  }}] }}],
+  "materials": [{{ "name": "red", "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0, 0, 0.5] }} }}],
+  "buffers": [{buffer}],
+  "bufferViews": [
+    {{ "buffer": 0, "byteOffset": 0, "byteLength": 72 }},
+    {{ "buffer": 0, "byteOffset": 72, "byteLength": 24 }},
+    {{ "buffer": 0, "byteOffset": 96, "byteLength": 6 }}
+  ],
+  "accessors": [
+    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }},
+    {{ "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3, "type": "VEC3" }},
+    {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }},
+    {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+  ]
+}}"#,
+            buffer = buffer
+        )
+    }
+
+    fn check_triangle(model: &Model) {
+        assert!(model.faces.len() == 1);
+        let face = &model.faces[0];
+        // the node moves the triangle away from the camera
+        assert!(model.vertex(face, 1) == Vertex3::init(1.0, 0.0, -2.0));
+        assert!(model.vertex_normal(face, 2) == Vertex3::init(0.0, 0.0, 1.0));
+        // v is flipped to match the way textures are loaded
+        assert!(model.vertex_uv(face, 2) == Vertex3::init(0.0, 0.0, 0.0));
+        assert!(model.vertex_uv(face, 0) == Vertex3::init(0.0, 1.0, 0.0));
+        assert!(face.tangents.is_some());
+        assert!(model.meshes == vec![Mesh { name: "tri".to_string(), faces: 0..1, material: Some(0) }]);
+        let material = model.material(face).unwrap();
+        assert!(material.name == "red");
+        assert!(material.diffuse == Vertex3::init(1.0, 0.0, 0.0) && material.dissolve == 0.5);
+    }
+
+    #[test]
+    fn loads_gltf_with_embedded_buffers() {
+        let uri = format!("data:application/octet-stream;base64,{}", STANDARD.encode(triangle_buffer()));
+        let json = triangle_json(&format!(r#"{{ "byteLength": 102, "uri": "{}" }}"#, uri));
+        let model = from_slice(json.as_bytes(), Path::new("tri.gltf")).unwrap();
+        check_triangle(&model);
+    }
+
+    #[test]
+    fn loads_binary_gltf() {
+        let mut json = triangle_json(r#"{ "byteLength": 102 }"#).into_bytes();
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+        let mut bin = triangle_buffer();
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        let model = from_slice(&glb, Path::new("tri.glb")).unwrap();
+        check_triangle(&model);
+    }
+
+    #[test]
+    fn reports_missing_external_buffers() {
+        let json = triangle_json(r#"{ "byteLength": 102, "uri": "missing%20file.bin" }"#);
+        match from_slice(json.as_bytes(), Path::new("models/tri.gltf")) {
+            Err(ModelError::Open { ref path, .. }) if path == Path::new("models/missing file.bin") => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match from_slice(b"{ not json", Path::new("tri.gltf")) {
+            Err(ModelError::Gltf { .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn reports_attributes_shorter_than_positions() {
+        let uri = format!("data:application/octet-stream;base64,{}", STANDARD.encode(triangle_buffer()));
+        let json = triangle_json(&format!(r#"{{ "byteLength": 102, "uri": "{}" }}"#, uri))
+            .replace(r#""count": 3, "type": "VEC2""#, r#""count": 2, "type": "VEC2""#);
+        match from_slice(json.as_bytes(), Path::new("tri.gltf")) {
+            Err(ModelError::Gltf { ref message, .. }) if message.starts_with("TEXCOORD_0") => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn strips_and_fans_keep_their_winding() {
+        let strip = triangles(Mode::TriangleStrip, &[0, 1, 2, 3]).unwrap();
+        assert!(strip == vec![[0, 1, 2], [2, 1, 3]]);
+        let fan = triangles(Mode::TriangleFan, &[0, 1, 2, 3]).unwrap();
+        assert!(fan == vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(triangles(Mode::Lines, &[0, 1]).is_none());
+    }
+}
@@ -1,68 +1,177 @@
+extern crate base64;
+extern crate gltf;
 extern crate image;
 extern crate sdl2;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
-use std::time::Duration;
 
+use cli::{ConvertOptions, RenderOptions};
+use framebuffer::{CanvasTarget, Framebuffer, RenderTarget};
 use geometry::Vertex3;
 use model::Model;
-use renderer::GouradShader;
-use std::f32;
-use std::fs::File;
-use std::path::Path;
-
+use renderer::{Interpolation, Scene};
+use shadow::ShadowSettings;
+use std::env;
+use std::process;
+
+pub mod bounds;
+pub mod cli;
+pub mod clipping;
+pub mod export;
+pub mod framebuffer;
 pub mod geometry;
+pub mod gltf_import;
+pub mod material;
 pub mod model;
+pub mod normals;
+pub mod ply;
 pub mod renderer;
+pub mod shadow;
+pub mod stl;
+pub mod tangents;
+pub mod texture;
+pub mod tiled;
+pub mod triangulate;
+pub mod vertex_buffer;
 
 const WIDTH: u32 = 1024;
 const HEIGHT: u32 = 768;
-const DEPTH: u32 = 255;
 const MOVEMENT_MAGNITUDE: f32 = 1.0;
-const ZBUFFER_SIZE: usize = ((WIDTH + 1) * (HEIGHT + 1)) as usize;
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("render") => match RenderOptions::parse(&args[2..]) {
+            Ok(options) => render(&options),
+            Err(message) => {
+                eprintln!("{}\n{}", message, cli::USAGE);
+                process::exit(1);
+            }
+        },
+        Some("convert") => match ConvertOptions::parse(&args[2..]) {
+            Ok(options) => convert(&options),
+            Err(message) => {
+                eprintln!("{}\n{}", message, cli::USAGE);
+                process::exit(1);
+            }
+        },
+        Some(other) => {
+            eprintln!("unknown command {}\n{}", other, cli::USAGE);
+            process::exit(1);
+        }
+        None => interactive(),
+    }
+}
+
+/// Renders a single frame headlessly and writes it out as an image.
+fn render(options: &RenderOptions) {
+    let mut model = match Model::load_with(&options.model, options.normals) {
+        Ok(model) => model,
+        Err(error) => {
+            eprintln!("could not load {}: {}", options.model.display(), error);
+            process::exit(1);
+        }
+    };
+    for texture in model.textures_mut() {
+        texture.sampler.anisotropy = options.anisotropy;
+    }
+    let mut scene = Scene::new(options.eye);
+    // with --frame, the model fills the view whatever its size and position
+    if options.frame {
+        if let Some(sphere) = model.bounding_sphere() {
+            scene.frame(sphere);
+        }
+    }
+    scene.light_dir = options.light_dir;
+    scene.shading = options.shading;
+    scene.interpolation = options.interpolation;
+    scene.threads = options.threads;
+    scene.shadows = options.shadows;
+    scene.blend = options.blend;
+    scene.alpha_cutoff = options.alpha_cutoff;
+    let mut framebuffer = Framebuffer::new(options.width, options.height);
+
+    if options.meshes.is_empty() {
+        scene.render(&model, &mut framebuffer);
+    } else {
+        let mut meshes = Vec::new();
+        for name in options.meshes.iter() {
+            let before = meshes.len();
+            meshes.extend(model.meshes_named(name));
+            if meshes.len() == before {
+                eprintln!("{} has no mesh named {}", options.model.display(), name);
+                process::exit(1);
+            }
+        }
+        scene.render_meshes(&model, &meshes, &mut framebuffer);
+    }
+
+    if let Err(error) = framebuffer.to_image().save(&options.out) {
+        eprintln!("could not write {}: {}", options.out.display(), error);
+        process::exit(1);
+    }
+}
+
+/// Loads a model, generating whatever normals it lacks, and writes it out
+/// in the format its output path asks for.
+fn convert(options: &ConvertOptions) {
+    let model = match Model::load_with(&options.model, options.normals) {
+        Ok(model) => model,
+        Err(error) => {
+            eprintln!("could not load {}: {}", options.model.display(), error);
+            process::exit(1);
+        }
+    };
+    let saved = match options.out.extension().and_then(|extension| extension.to_str()) {
+        Some("ply") => model.save_ply(&options.out),
+        _ => model.save_obj(&options.out),
+    };
+    if let Err(error) = saved {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn interactive() {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
     let window = video_subsystem
-        .window("pocket-renderer", 1024, 768)
+        .window("pocket-renderer", WIDTH, HEIGHT)
         .position_centered()
         .opengl()
         .build()
         .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
+    let mut target = CanvasTarget::new(window.into_canvas().build().unwrap());
 
     // Draw a black canvas
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
+    target.clear(Color::RGB(0, 0, 0));
+    target.present();
 
     // Setup event pump
     let mut event_pump = sdl_context.event_pump().unwrap();
 
     // Load the model
-    let model = Model::new("african_head");
-    let up = Vertex3::init(0.0, 1.0, 0.0);
-    let mut eye = Vertex3::init(1.0, 1.0, 3.0);
-    let mut light_dir = Vertex3::init(1.0, 1.0, 1.0);
-    let center = Vertex3::new();
-
-    // let uniform_m = projection.clone() * model_view.clone();
-    // let uniform_mit = uniform_m.invert_transpose();
-
-    let mut zbuffer: [f32; ZBUFFER_SIZE] = [f32::NEG_INFINITY; ZBUFFER_SIZE];
+    let model = match Model::new("african_head") {
+        Ok(model) => model,
+        Err(error) => {
+            eprintln!("could not load the model: {}", error);
+            process::exit(1);
+        }
+    };
+    let mut scene = Scene::new(Vertex3::init(1.0, 1.0, 3.0));
+    if let Some(sphere) = model.bounding_sphere() {
+        scene.frame(sphere);
+    }
+    // the arrow keys move the eye by the radius of the model
+    let step = MOVEMENT_MAGNITUDE / scene.zoom;
+    scene.threads = cli::available_threads();
 
     // event loop
     'running: loop {
-        let mut render_count = 0;
-        canvas.clear();
-
-        let viewport = renderer::viewport(WIDTH / 6, HEIGHT / 4, WIDTH * 2 / 3, HEIGHT * 2 / 3, DEPTH);
-        let model_view = renderer::lookat(eye, center, up);
-        let projection = renderer::projection(eye, center);
+        target.clear(Color::RGB(0, 0, 0));
 
         for event in event_pump.poll_iter() {
 
@@ -76,51 +185,51 @@ fn main() {
                     keycode: Some(Keycode::Left),
                     ..
                 } => {
-                    eye.x += 1.0 * MOVEMENT_MAGNITUDE;
+                    scene.eye.x += step;
                 },
                 Event::KeyDown {
                     keycode: Some(Keycode::Right),
                     ..
                 } => {
-                    eye.x -= 1.0 * MOVEMENT_MAGNITUDE;
+                    scene.eye.x -= step;
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::Up),
                     ..
                 } => {
-                    eye.y += 1.0 * MOVEMENT_MAGNITUDE;
+                    scene.eye.y += step;
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::Down),
                     ..
                 } => {
-                    eye.y -= 1.0 * MOVEMENT_MAGNITUDE;
+                    scene.eye.y -= step;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    ..
+                } => {
+                    scene.shadows = match scene.shadows {
+                        Some(_) => None,
+                        None => Some(ShadowSettings::new()),
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    scene.interpolation = match scene.interpolation {
+                        Interpolation::Perspective => Interpolation::Affine,
+                        Interpolation::Affine => Interpolation::Perspective,
+                    };
                 }
                 _ => {}
             }
         }
 
         // draw stuff
-        for face in model.faces.clone() {
-            let mut screen_coords: [Vertex3<f32>; 3] = [Vertex3::new(); 3];
-            let mut world_coords: [Vertex3<f32>; 3] = [Vertex3::new(); 3];
-
-            for i in 0..3 {
-                let vertex_index = face.get_vertex(i) as usize;
-                world_coords[i] = *model.verts.get(vertex_index).unwrap();
-                screen_coords[i] = (viewport.clone()
-                    * projection.clone()
-                    * model_view.clone()
-                    * world_coords[i].to_matrix())
-                .to_vector();
-            }
-            let shader = GouradShader::new(&model, &face, light_dir.normalize());
-
-            render_count += renderer::triangle(&screen_coords, shader, &mut zbuffer, &mut canvas);
-        }
+        scene.render(&model, &mut target);
 
-        canvas.present();
-        render_count = 0;
-        zbuffer = [f32::NEG_INFINITY; ZBUFFER_SIZE];
+        target.present();
     }
 }
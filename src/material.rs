@@ -0,0 +1,213 @@
+use image::Rgba;
+use std::io::prelude::*;
+use std::path::Path;
+use std::vec::Vec;
+
+use geometry::{Derivatives, Vertex3};
+use model::{open_texture, parse_floats, ModelError};
+use texture::{Texture, Wrap};
+
+/// Surface properties from an MTL material library. Colors are in [0, 1].
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+    /// `Ka`
+    pub ambient: Vertex3<f32>,
+    /// `Kd`, multiplied with the diffuse map when there is one.
+    pub diffuse: Vertex3<f32>,
+    /// `Ks`, multiplied with the specular map when there is one.
+    pub specular: Vertex3<f32>,
+    /// `Ns`, the specular exponent.
+    pub shininess: f32,
+    /// `d`, or one minus `Tr`. 1 is fully opaque.
+    pub dissolve: f32,
+    /// `map_Kd`
+    pub diffuse_map: Option<Texture>,
+    /// `map_Bump` or `bump`. Expected to be a tangent-space normal map, as
+    /// most exporters write them.
+    pub bump_map: Option<Texture>,
+    /// `map_Ks`
+    pub specular_map: Option<Texture>,
+}
+
+impl Material {
+    /// A plain white, opaque material without any maps.
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: Vertex3::new(),
+            diffuse: Vertex3::init(1.0, 1.0, 1.0),
+            specular: Vertex3::new(),
+            shininess: 1.0,
+            dissolve: 1.0,
+            diffuse_map: None,
+            bump_map: None,
+            specular_map: None,
+        }
+    }
+
+    /// Parses an MTL file. Texture maps are loaded relative to `dir`; maps
+    /// whose files are missing are left out with a warning.
+    pub fn parse_library<R: BufRead>(reader: R, dir: &Path) -> Result<Vec<Material>, ModelError> {
+        let mut materials: Vec<Material> = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line_number = index + 1;
+            let decoded_line = line.map_err(|error| ModelError::Read {
+                line: line_number,
+                error,
+            })?;
+            let content = decoded_line.split('#').next().unwrap_or("");
+            let mut values = content.split_whitespace();
+            let statement = match values.next() {
+                Some(statement) => statement,
+                None => continue,
+            };
+            let values: Vec<&str> = values.collect();
+            let missing = || ModelError::MissingValues {
+                line: line_number,
+                statement: statement.to_string(),
+            };
+
+            if statement == "newmtl" {
+                let name = values.first().ok_or_else(missing)?;
+                materials.push(Material::new(name));
+                continue;
+            }
+            // everything else describes the most recent newmtl
+            let material = match materials.last_mut() {
+                Some(material) => material,
+                None => continue,
+            };
+            match statement {
+                "Ka" | "Kd" | "Ks" => {
+                    let rgb = parse_floats(&values, 1, statement, line_number)?;
+                    // a single value sets all three channels
+                    let color = match rgb.len() {
+                        1 | 2 => Vertex3::init(rgb[0], rgb[0], rgb[0]),
+                        _ => Vertex3::init(rgb[0], rgb[1], rgb[2]),
+                    };
+                    match statement {
+                        "Ka" => material.ambient = color,
+                        "Kd" => material.diffuse = color,
+                        _ => material.specular = color,
+                    }
+                }
+                "Ns" => material.shininess = parse_floats(&values, 1, statement, line_number)?[0],
+                "d" => material.dissolve = parse_floats(&values, 1, statement, line_number)?[0],
+                "Tr" => material.dissolve = 1.0 - parse_floats(&values, 1, statement, line_number)?[0],
+                "map_Kd" | "map_Bump" | "map_bump" | "bump" | "map_Ks" => {
+                    // options such as `-bm 0.5` come before the file name
+                    let file = values.last().ok_or_else(missing)?;
+                    let path = dir.join(file);
+                    // a missing map only loses some detail, one that can't
+                    // be decoded is still an error
+                    let map = if path.exists() {
+                        let mut map = open_texture(&path)?;
+                        if values.windows(2).any(|option| option == ["-clamp", "on"]) {
+                            map.sampler.wrap_u = Wrap::ClampToEdge;
+                            map.sampler.wrap_v = Wrap::ClampToEdge;
+                        }
+                        Some(map)
+                    } else {
+                        eprintln!("warning: line {}: {} not found, ignoring it", line_number, path.display());
+                        None
+                    };
+                    match statement {
+                        "map_Kd" => material.diffuse_map = map,
+                        "map_Ks" => material.specular_map = map,
+                        _ => material.bump_map = map,
+                    }
+                }
+                // illumination models, emission, refraction and other maps are ignored
+                _ => {}
+            }
+        }
+
+        Ok(materials)
+    }
+
+    /// Diffuse color at `uv`: the diffuse map, if any, tinted by `Kd`, with
+    /// the map's alpha scaled by `d`. `duv` picks the map's level of detail.
+    pub fn diffuse_at(&self, uv: Vertex3<f32>, duv: Derivatives) -> Rgba<f32> {
+        tint(self.diffuse_map.as_ref(), uv, duv, self.diffuse, self.dissolve)
+    }
+
+    /// Specular color at `uv`: the specular map, if any, tinted by `Ks`.
+    pub fn specular_at(&self, uv: Vertex3<f32>, duv: Derivatives) -> Rgba<f32> {
+        tint(self.specular_map.as_ref(), uv, duv, self.specular, 1.0)
+    }
+}
+
+fn tint(map: Option<&Texture>, uv: Vertex3<f32>, duv: Derivatives, color: Vertex3<f32>, alpha: f32) -> Rgba<f32> {
+    let texel = match map {
+        Some(map) => map.sample(uv, duv),
+        None => Rgba([1.0; 4]),
+    };
+    let channel = |c: f32, k: f32| c * k.clamp(0.0, 1.0);
+    Rgba([
+        channel(texel[0], color.x),
+        channel(texel[1], color.y),
+        channel(texel[2], color.z),
+        channel(texel[3], alpha),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(mtl: &str) -> Result<Vec<Material>, ModelError> {
+        Material::parse_library(mtl.as_bytes(), Path::new("models"))
+    }
+
+    #[test]
+    fn parses_material_constants() {
+        let materials = parse(
+            "# two materials\n\
+             newmtl skin\n\
+             Ka 0.1 0.1 0.1\n\
+             Kd 0.8 0.5 0.25\n\
+             Ks 0.5\n\
+             Ns 32\n\
+             d 0.75\n\
+             illum 2\n\
+             \n\
+             newmtl glass\n\
+             Tr 0.9\n",
+        )
+        .unwrap();
+        assert!(materials.len() == 2);
+        let skin = &materials[0];
+        assert!(skin.name == "skin");
+        assert!(skin.ambient == Vertex3::init(0.1, 0.1, 0.1));
+        assert!(skin.diffuse == Vertex3::init(0.8, 0.5, 0.25));
+        assert!(skin.specular == Vertex3::init(0.5, 0.5, 0.5));
+        assert!(skin.shininess == 32.0 && skin.dissolve == 0.75);
+        assert!((materials[1].dissolve - 0.1).abs() < 1e-6);
+        assert!(materials[1].diffuse == Vertex3::init(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn diffuse_color_is_tinted_by_kd() {
+        let mut material = Material::new("red");
+        material.diffuse = Vertex3::init(1.0, 0.5, 0.0);
+        material.dissolve = 0.25;
+        assert!(material.diffuse_at(Vertex3::new(), Derivatives::zero()) == Rgba([1.0, 0.5, 0.0, 0.25]));
+    }
+
+    #[test]
+    fn loads_texture_maps_relative_to_the_library() {
+        let materials = parse("newmtl head\nmap_Kd african_head_diffuse.png\nmap_Bump -bm 1 african_head_nm.png\n").unwrap();
+        assert!(materials[0].diffuse_map.is_some());
+        assert!(materials[0].bump_map.is_some());
+        assert!(materials[0].specular_map.is_none());
+        let materials = parse("newmtl head\nmap_Kd missing.png\nKd 1 0 0\n").unwrap();
+        assert!(materials[0].diffuse_map.is_none());
+        assert!(materials[0].diffuse == Vertex3::init(1.0, 0.0, 0.0));
+        match parse("newmtl head\nmap_Kd african_head.obj\n") {
+            Err(ModelError::Texture { .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+}
@@ -1,31 +1,142 @@
 extern crate image;
 
-use image::DynamicImage;
+use image::{ImageError, Rgb, Rgba};
+use std::error::Error;
 use std::f32;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::ops::Deref;
-use std::path::Path;
+use std::io::{BufReader, BufWriter};
+use std::iter;
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::vec::Vec;
 
-use geometry::Vertex3;
+use bounds::{BoundingBox, BoundingSphere};
+use export;
+use geometry::{Derivatives, Matrix4, Vertex3, Vertex4};
+use gltf_import;
+use material::Material;
+use normals::{self, NormalSettings};
+use ply;
+use stl;
+use tangents;
+use texture::Texture;
+use triangulate::triangulate;
+use vertex_buffer::VertexBuffer;
 
 pub struct Model {
     pub verts: Vec<Vertex3<f32>>,
     pub textures: Vec<Vertex3<f32>>,
     pub normals: Vec<Vertex3<f32>>,
+    /// Tangents with the handedness of the texture space in `w`, see
+    /// `tangents::generate`.
+    pub tangents: Vec<Vertex4<f32>>,
+    /// Colors in [0, 1] for each of `verts`, or empty when the file has
+    /// none. Faces without texture coordinates are drawn with them.
+    pub colors: Vec<Vertex3<f32>>,
     pub faces: Vec<Face>,
-    pub texture_image: DynamicImage,
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub texture_image: Texture,
+    pub normal_map: Option<Texture>,
+    pub tangent_normal_map: Option<Texture>,
+    // built on first use, see `vertex_buffer`
+    vertex_buffer: OnceLock<VertexBuffer>,
 }
 
+/// A triangle. Texture and normal indices are only present when every corner
+/// of the face in the OBJ file had them. `material` indexes into the model's
+/// materials when the face came after a `usemtl`.
 #[derive(Debug, Copy, Clone)]
 pub struct Face {
     pub vertexes: [u32; 3],
-    pub textures: [u32; 3],
-    pub normals: [u32; 3],
+    pub textures: Option<[u32; 3]>,
+    pub normals: Option<[u32; 3]>,
+    pub tangents: Option<[u32; 3]>,
+    pub material: Option<usize>,
+    /// The `s` group the face is smoothed with, or None when smoothing is
+    /// off. Faces before any `s` statement are smoothed together in group 0.
+    pub smoothing_group: Option<u32>,
 }
 
+/// A named part of a model: a run of consecutive faces from the same `o` or
+/// `g` statement that share a material. A group that switches material
+/// part way through is split into several meshes with the same name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub name: String,
+    pub faces: Range<usize>,
+    pub material: Option<usize>,
+}
+
+impl Mesh {
+    pub fn faces_len(&self) -> usize {
+        self.faces.len()
+    }
+}
+
+/// Everything that can go wrong loading a model. Variants that come from the
+/// OBJ file itself carry the (1-based) line they were found on.
+#[derive(Debug)]
+pub enum ModelError {
+    Open { path: PathBuf, error: io::Error },
+    Write { path: PathBuf, error: io::Error },
+    Read { line: usize, error: io::Error },
+    Texture { path: PathBuf, error: ImageError },
+    Library { path: PathBuf, error: Box<ModelError> },
+    MissingValues { line: usize, statement: String },
+    InvalidNumber { line: usize, value: String },
+    InvalidIndex { line: usize, value: String },
+    IndexOutOfRange { line: usize, value: String, count: usize },
+    MixedFaceFormats { line: usize },
+    /// A glTF file that couldn't be parsed or refers to data it lacks.
+    Gltf { path: PathBuf, message: String },
+    /// STL or PLY data that doesn't follow the format.
+    Malformed { format: &'static str, message: String },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModelError::Open { ref path, ref error } => {
+                write!(f, "could not open {}: {}", path.display(), error)
+            }
+            ModelError::Write { ref path, ref error } => {
+                write!(f, "could not write {}: {}", path.display(), error)
+            }
+            ModelError::Read { line, ref error } => write!(f, "line {}: could not read: {}", line, error),
+            ModelError::Texture { ref path, ref error } => {
+                write!(f, "could not load texture {}: {}", path.display(), error)
+            }
+            ModelError::Library { ref path, ref error } => write!(f, "in {}: {}", path.display(), error),
+            ModelError::MissingValues { line, ref statement } => {
+                write!(f, "line {}: not enough values for `{}`", line, statement)
+            }
+            ModelError::InvalidNumber { line, ref value } => {
+                write!(f, "line {}: `{}` is not a number", line, value)
+            }
+            ModelError::InvalidIndex { line, ref value } => {
+                write!(f, "line {}: `{}` is not a valid index", line, value)
+            }
+            ModelError::IndexOutOfRange { line, ref value, count } => {
+                write!(f, "line {}: index `{}` is out of range, only {} defined so far", line, value, count)
+            }
+            ModelError::MixedFaceFormats { line } => {
+                write!(f, "line {}: face corners mix different index formats", line)
+            }
+            ModelError::Gltf { ref path, ref message } => {
+                write!(f, "invalid glTF {}: {}", path.display(), message)
+            }
+            ModelError::Malformed { format, ref message } => write!(f, "invalid {} data: {}", format, message),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
 impl Deref for Model {
     type Target = Vec<Face>;
 
@@ -39,87 +150,440 @@ impl Face {
         self.vertexes[i]
     }
 
-    pub fn get_texture(&self, i: usize) -> u32 {
-        self.textures[i]
+    pub fn get_texture(&self, i: usize) -> Option<u32> {
+        self.textures.map(|textures| textures[i])
     }
 
-    pub fn get_normal(&self, i: usize) -> u32 {
-        self.normals[i]
+    pub fn get_normal(&self, i: usize) -> Option<u32> {
+        self.normals.map(|normals| normals[i])
     }
 }
 
 impl Model {
-    pub fn new(path: &str) -> Model {
+    pub fn new(name: &str) -> Result<Model, ModelError> {
+        Model::load(Path::new(&format!("models/{}.obj", name)))
+    }
+
+    /// Loads an OBJ file from an arbitrary path, along with the material
+    /// libraries it names in `mtllib`, which are looked up relative to it.
+    ///
+    /// Faces without a material use the diffuse texture found next to the
+    /// OBJ file as `<stem>_diffuse.png`; object-space and tangent-space
+    /// normal maps are picked up from `<stem>_nm.png` and
+    /// `<stem>_nm_tangent.png` when present. Each of them may be in any of
+    /// the `TEXTURE_EXTENSIONS` formats instead of PNG. Models without a
+    /// diffuse texture are drawn in plain white.
+    pub fn load(path: &Path) -> Result<Model, ModelError> {
+        Model::load_with(path, NormalSettings::new())
+    }
+
+    /// Like `load`, generating the normals missing from the file with the
+    /// given settings.
+    ///
+    /// Files ending in `.gltf` or `.glb` are loaded with
+    /// `gltf_import::load` instead, which brings its own materials and
+    /// leaves primitives without normals flat. `.stl` and `.ply` files go
+    /// to `stl::load` and `ply::load`.
+    pub fn load_with(path: &Path, normal_settings: NormalSettings) -> Result<Model, ModelError> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("gltf") | Some("glb") => return gltf_import::load(path),
+            Some("stl") => return stl::load(path),
+            Some("ply") => return ply::load(path, normal_settings),
+            _ => {}
+        }
+        let file = File::open(path).map_err(|error| ModelError::Open {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let (mut model, libraries) = Model::parse_obj(BufReader::new(file))?;
+        model.generate_normals(normal_settings);
+        model.generate_tangents();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for library in libraries.iter() {
+            let library_path = dir.join(library);
+            let file = File::open(&library_path).map_err(|error| ModelError::Open {
+                path: library_path.clone(),
+                error,
+            })?;
+            let materials = Material::parse_library(BufReader::new(file), dir).map_err(|error| ModelError::Library {
+                path: library_path.clone(),
+                error: Box::new(error),
+            })?;
+            model.add_materials(materials);
+        }
+        if let Some(texture_image) = load_texture(path, "diffuse")? {
+            model.texture_image = texture_image;
+        }
+        model.normal_map = load_texture(path, "nm")?;
+        model.tangent_normal_map = load_texture(path, "nm_tangent")?;
+        Ok(model)
+    }
+
+    /// A model without any geometry or materials, drawn in plain white.
+    pub fn empty() -> Model {
+        Model {
+            verts: Vec::new(),
+            textures: Vec::new(),
+            normals: Vec::new(),
+            tangents: Vec::new(),
+            colors: Vec::new(),
+            faces: Vec::new(),
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            texture_image: Texture::from_color(Rgb([255, 255, 255])),
+            normal_map: None,
+            tangent_normal_map: None,
+            vertex_buffer: OnceLock::new(),
+        }
+    }
+
+    /// Parses OBJ data without looking for any textures or material
+    /// libraries. Faces are still tagged with the materials named by
+    /// `usemtl`, which are left plain white.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Model, ModelError> {
+        let (mut model, _) = Model::parse_obj(reader)?;
+        model.generate_normals(NormalSettings::new());
+        model.generate_tangents();
+        Ok(model)
+    }
+
+    // Also returns the material libraries the file refers to.
+    fn parse_obj<R: BufRead>(reader: R) -> Result<(Model, Vec<String>), ModelError> {
         let mut verts: Vec<Vertex3<f32>> = Vec::new();
         let mut textures: Vec<Vertex3<f32>> = Vec::new();
         let mut normals: Vec<Vertex3<f32>> = Vec::new();
         let mut faces: Vec<Face> = Vec::new();
-        let file = File::open(Path::new(&format!("models/{}.obj", path)));
-        let buf_reader = BufReader::new(file.unwrap());
-        let texture_image = image::open(Path::new(&format!("models/{}_diffuse.png", path)));
-        let texture_image = match texture_image {
-            Ok(file) => {
-                println!("Loaded texture file...");
-                file.flipv()
+        let mut materials: Vec<Material> = Vec::new();
+        let mut libraries: Vec<String> = Vec::new();
+        let mut material: Option<usize> = None;
+        let mut smoothing_group = Some(0);
+        // faces before any `o` or `g` belong to the default group
+        let mut meshes = vec![Mesh {
+            name: "default".to_string(),
+            faces: 0..0,
+            material: None,
+        }];
+
+        for (index, line) in reader.lines().enumerate() {
+            let line_number = index + 1;
+            let decoded_line = line.map_err(|error| ModelError::Read {
+                line: line_number,
+                error,
+            })?;
+            // everything after a # is a comment
+            let content = decoded_line.split('#').next().unwrap_or("");
+            let mut values = content.split_whitespace();
+            let statement = match values.next() {
+                Some(statement) => statement,
+                None => continue,
+            };
+            let values: Vec<&str> = values.collect();
+
+            match statement {
+                // parse out all the vertices
+                "v" => {
+                    let coords = parse_floats(&values, 3, statement, line_number)?;
+                    verts.push(Vertex3::init(coords[0], coords[1], coords[2]));
+                }
+                // get the texture coordinates, the optional w is ignored
+                "vt" => {
+                    let coords = parse_floats(&values, 1, statement, line_number)?;
+                    let v = coords.get(1).cloned().unwrap_or(0.0);
+                    textures.push(Vertex3::init(coords[0], v, 0.0));
+                }
+                "vn" => {
+                    let coords = parse_floats(&values, 3, statement, line_number)?;
+                    normals.push(Vertex3::init(coords[0], coords[1], coords[2]));
+                }
+                // parse out the faces which are of the following format
+                // f vertex0_idx/texture_idx/normal_idx vertex1_idx/...
+                // where the texture and normal indices are optional and
+                // negative indices count back from the last element read
+                "f" => {
+                    if values.len() < 3 {
+                        return Err(ModelError::MissingValues {
+                            line: line_number,
+                            statement: statement.to_string(),
+                        });
+                    }
+                    let counts = [verts.len(), textures.len(), normals.len()];
+                    let corners = values
+                        .iter()
+                        .map(|value| parse_face_string(value, counts, line_number))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let face = Face {
+                        vertexes: [0; 3],
+                        textures: None,
+                        normals: None,
+                        tangents: None,
+                        material,
+                        smoothing_group,
+                    };
+                    faces.extend(build_faces(&corners, &verts, face, line_number)?);
+                    if let Some(mesh) = meshes.last_mut() {
+                        mesh.faces.end = faces.len();
+                    }
+                }
+                "s" => {
+                    smoothing_group = match values.first() {
+                        None => {
+                            return Err(ModelError::MissingValues {
+                                line: line_number,
+                                statement: statement.to_string(),
+                            })
+                        }
+                        Some(&"off") | Some(&"0") => None,
+                        Some(value) => Some(value.parse().map_err(|_| ModelError::InvalidNumber {
+                            line: line_number,
+                            value: value.to_string(),
+                        })?),
+                    };
+                }
+                "o" | "g" => {
+                    let name = if values.is_empty() {
+                        "default".to_string()
+                    } else {
+                        values.join(" ")
+                    };
+                    start_mesh(&mut meshes, name, material, faces.len());
+                }
+                "mtllib" => {
+                    if values.is_empty() {
+                        return Err(ModelError::MissingValues {
+                            line: line_number,
+                            statement: statement.to_string(),
+                        });
+                    }
+                    libraries.extend(values.iter().map(|library| library.to_string()));
+                }
+                "usemtl" => {
+                    let name = values.first().ok_or_else(|| ModelError::MissingValues {
+                        line: line_number,
+                        statement: statement.to_string(),
+                    })?;
+                    material = match materials.iter().position(|m| m.name == *name) {
+                        Some(index) => Some(index),
+                        None => {
+                            materials.push(Material::new(name));
+                            Some(materials.len() - 1)
+                        }
+                    };
+                    let group = meshes.last().map(|mesh| mesh.name.clone()).unwrap_or_default();
+                    start_mesh(&mut meshes, group, material, faces.len());
+                }
+                // anything else is ignored
+                _ => {}
             }
-            Err(error) => panic!("There was a problems opening the texture file: {:?}", error),
+        }
+
+        meshes.retain(|mesh| !mesh.faces.is_empty());
+        let model = Model {
+            verts,
+            faces,
+            meshes,
+            normals,
+            textures,
+            materials,
+            ..Model::empty()
         };
-        for line in buf_reader.lines() {
-            let decoded_line = line.unwrap();
-            let values: Vec<&str> = decoded_line.split(" ").collect();
-
-            // parse out all the vertices
-            if values[0] == "v" {
-                let x: f32 = values[1].parse().unwrap();
-                let y: f32 = values[2].parse().unwrap();
-                let z: f32 = values[3].parse().unwrap();
-                verts.push(Vertex3 { x: x, y: y, z: z });
-            }
+        Ok((model, libraries))
+    }
 
-            // get the texture coordinates
-            if values[0] == "vt" {
-                let x: f32 = values[2].parse().unwrap();
-                let y: f32 = values[3].parse().unwrap();
-                textures.push(Vertex3 { x: x, y: y, z: 0.0 });
+    // Fills in the materials named by `usemtl` with their definitions from
+    // a library. Materials that are never used are kept as well.
+    fn add_materials(&mut self, materials: Vec<Material>) {
+        for material in materials {
+            match self.materials.iter().position(|m| m.name == material.name) {
+                Some(index) => self.materials[index] = material,
+                None => self.materials.push(material),
             }
+        }
+    }
 
-            if values[0] == "vn" {
-                let x: f32 = values[2].parse().unwrap();
-                let y: f32 = values[3].parse().unwrap();
-                let z: f32 = values[4].parse().unwrap();
-                normals.push(Vertex3 { x: x, y: y, z: z });
-            }
+    /// Fills in vertex normals for the smoothed faces that have none, see
+    /// `normals::generate`.
+    pub fn generate_normals(&mut self, settings: NormalSettings) {
+        normals::generate(self, settings);
+        self.invalidate_vertex_buffer();
+    }
 
-            // parse out the faces which are of the following format
-            // f vertex0_idx/texture_idx/normal_idx vertex1_idx/...
-            if values[0] == "f" {
-                let mut face = Face {
-                    vertexes: [0; 3],
-                    textures: [0; 3],
-                    normals: [0; 3],
-                };
-                for i in 0..3 {
-                    face.vertexes[i] = *parse_face_string(values[i + 1]).get(0).unwrap();
-                    face.textures[i] = *parse_face_string(values[i + 1]).get(1).unwrap();
-                    face.normals[i] = *parse_face_string(values[i + 1]).get(2).unwrap();
-                }
-                faces.push(face);
-            }
+    /// Replaces the tangents of every textured face, see
+    /// `tangents::generate`. Call again after changing the normals.
+    pub fn generate_tangents(&mut self) {
+        self.tangents.clear();
+        for face in self.faces.iter_mut() {
+            face.tangents = None;
         }
-        Model {
-            verts: verts,
-            faces: faces,
-            normals: normals,
-            textures: textures,
-            texture_image: texture_image,
+        tangents::generate(self);
+    }
+
+    /// The faces as an indexed buffer of unique vertices, built the first
+    /// time it's asked for.
+    pub fn vertex_buffer(&self) -> &VertexBuffer {
+        self.vertex_buffer.get_or_init(|| VertexBuffer::new(self))
+    }
+
+    /// Drops the vertex buffer so it's rebuilt on next use. Needed after
+    /// changing the geometry of the model other than through its methods.
+    pub fn invalidate_vertex_buffer(&mut self) {
+        self.vertex_buffer = OnceLock::new();
+    }
+
+    /// Writes the model as an OBJ file, see `export::write_obj`. The
+    /// materials go into an MTL library next to it with the same stem.
+    pub fn save_obj(&self, path: &Path) -> Result<(), ModelError> {
+        let library = if self.materials.is_empty() {
+            None
+        } else {
+            let library_path = path.with_extension("mtl");
+            write_file(&library_path, |writer| export::write_mtl(&self.materials, writer))?;
+            library_path.file_name().map(|name| name.to_string_lossy().into_owned())
+        };
+        write_file(path, |writer| export::write_obj(self, writer, library.as_deref()))
+    }
+
+    /// Writes the model as a binary PLY file, see `export::write_ply`.
+    pub fn save_ply(&self, path: &Path) -> Result<(), ModelError> {
+        write_file(path, |writer| export::write_ply(self, writer))
+    }
+
+    /// The box around every vertex of the model, or None if it has none.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::from_points(&self.verts)
+    }
+
+    /// A sphere around every vertex of the model, or None if it has none.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(&self.verts)
+    }
+
+    /// The transform that centers the model on the origin and scales it to
+    /// fit in a sphere of radius 1, see `BoundingSphere::normalize_transform`.
+    pub fn normalize_transform(&self) -> Matrix4 {
+        match self.bounding_sphere() {
+            Some(sphere) => sphere.normalize_transform(),
+            None => Matrix4::identity(),
+        }
+    }
+
+    /// Every mesh with the given name.
+    pub fn meshes_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Mesh> + 'a {
+        self.meshes.iter().filter(move |mesh| mesh.name == name)
+    }
+
+    /// The material a face was drawn with, if it had one.
+    pub fn material(&self, face: &Face) -> Option<&Material> {
+        face.material.map(|index| &self.materials[index])
+    }
+
+    /// Position of one corner of a face.
+    pub fn vertex(&self, face: &Face, i: usize) -> Vertex3<f32> {
+        self.verts[face.get_vertex(i) as usize]
+    }
+
+    /// Texture coordinates of one corner of a face, or the origin when the
+    /// face has none.
+    pub fn vertex_uv(&self, face: &Face, i: usize) -> Vertex3<f32> {
+        match face.get_texture(i) {
+            Some(index) => self.textures[index as usize],
+            None => Vertex3::new(),
+        }
+    }
+
+    /// Normal at one corner of a face. Faces without normals fall back to
+    /// the flat normal of the face.
+    pub fn vertex_normal(&self, face: &Face, i: usize) -> Vertex3<f32> {
+        match face.get_normal(i) {
+            Some(index) => self.normals[index as usize],
+            None => self.face_normal(face),
+        }
+    }
+
+    /// Tangent at one corner of a face with the handedness in `w`, if the
+    /// face has texture coordinates.
+    pub fn vertex_tangent(&self, face: &Face, i: usize) -> Option<Vertex4<f32>> {
+        face.tangents.map(|tangents| self.tangents[tangents[i] as usize])
+    }
+
+    /// Bitangent at one corner of a face, rebuilt from the normal and the
+    /// tangent the same way shaders do.
+    pub fn vertex_bitangent(&self, face: &Face, i: usize) -> Option<Vertex3<f32>> {
+        self.vertex_tangent(face, i).map(|tangent| {
+            let t = Vertex3::init(tangent.x, tangent.y, tangent.z);
+            Vertex3::cross(self.vertex_normal(face, i), t) * tangent.w
+        })
+    }
+
+    /// Color of one corner of a face, if the model has vertex colors.
+    pub fn vertex_color(&self, face: &Face, i: usize) -> Option<Vertex3<f32>> {
+        self.colors.get(face.get_vertex(i) as usize).cloned()
+    }
+
+    pub fn face_normal(&self, face: &Face) -> Vertex3<f32> {
+        let v0 = self.vertex(face, 0);
+        let n = Vertex3::cross(self.vertex(face, 1) - v0, self.vertex(face, 2) - v0);
+        if n.norm() > 0.0 {
+            n.normalize()
+        } else {
+            n
+        }
+    }
+
+    /// Diffuse color of a face at `uv`, from its material when it has one
+    /// and from the model's texture otherwise. `duv`, how `uv` changes from
+    /// one pixel to the next, picks the texture's level of detail here and
+    /// in the other lookups below.
+    pub fn diffuse(&self, face: &Face, uv: Vertex3<f32>, duv: Derivatives) -> Rgba<f32> {
+        match self.material(face) {
+            Some(material) => material.diffuse_at(uv, duv),
+            None => self.uv(uv, duv),
+        }
+    }
+
+    /// Whether a face lets what is behind it show through, because its
+    /// material's `d` is below 1 or its diffuse texture isn't opaque.
+    pub fn is_transparent(&self, face: &Face) -> bool {
+        match self.material(face) {
+            Some(material) => {
+                material.dissolve < 1.0 || material.diffuse_map.as_ref().is_some_and(|map| !map.is_opaque())
+            }
+            None => !self.texture_image.is_opaque(),
         }
     }
 
-    pub fn uv(&self, uv: Vertex3<f32>) -> &image::Rgb<u8> {
-        let imgbuf = self.texture_image.as_rgb8().unwrap();
-        let x = (uv.x * imgbuf.height() as f32) as u32;
-        let y = (uv.y * imgbuf.width() as f32) as u32;
-        imgbuf.get_pixel(x, y)
+    /// Every texture of the model and of its materials.
+    pub fn textures_mut(&mut self) -> impl Iterator<Item = &mut Texture> {
+        let maps = self.materials.iter_mut().flat_map(|material| {
+            vec![
+                material.diffuse_map.as_mut(),
+                material.bump_map.as_mut(),
+                material.specular_map.as_mut(),
+            ]
+        });
+        iter::once(&mut self.texture_image)
+            .chain(self.normal_map.as_mut())
+            .chain(self.tangent_normal_map.as_mut())
+            .chain(maps.flatten())
+    }
+
+    /// Color of the model's texture at `uv`.
+    pub fn uv(&self, uv: Vertex3<f32>, duv: Derivatives) -> Rgba<f32> {
+        self.texture_image.sample(uv, duv)
+    }
+
+    /// Object-space normal from the normal map, if the model has one.
+    pub fn normal(&self, uv: Vertex3<f32>, duv: Derivatives) -> Option<Vertex3<f32>> {
+        self.normal_map.as_ref().map(|map| sample_normal(map, uv, duv))
+    }
+
+    /// Tangent-space normal from the face's bump map, or from the model's
+    /// tangent normal map if the material has none.
+    pub fn tangent_normal(&self, face: &Face, uv: Vertex3<f32>, duv: Derivatives) -> Option<Vertex3<f32>> {
+        let bump_map = self.material(face).and_then(|material| material.bump_map.as_ref());
+        bump_map
+            .or(self.tangent_normal_map.as_ref())
+            .map(|map| sample_normal(map, uv, duv))
     }
 
     pub fn verts_len(&self) -> usize {
@@ -131,23 +595,450 @@ impl Model {
     }
 }
 
-fn parse_face_string(face_str: &str) -> Vec<u32> {
-    let parse_index = |string: &str| {
-        let value: u32 = string.parse().unwrap();
-        value - 1 // waveform object files start index at 1 rather than 0
+// Creates the file at `path` and fills it through a buffered writer.
+fn write_file<F>(path: &Path, write: F) -> Result<(), ModelError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let error = |error| ModelError::Write {
+        path: path.to_path_buf(),
+        error,
+    };
+    let mut writer = BufWriter::new(File::create(path).map_err(error)?);
+    write(&mut writer).map_err(error)?;
+    writer.flush().map_err(error)
+}
+
+// normal maps encode each component in [-1, 1] as a color channel in [0, 1]
+fn sample_normal(map: &Texture, uv: Vertex3<f32>, duv: Derivatives) -> Vertex3<f32> {
+    let pixel = map.sample(uv, duv);
+    let channel = |c: f32| c * 2.0 - 1.0;
+    Vertex3::init(channel(pixel[0]), channel(pixel[1]), channel(pixel[2])).normalize()
+}
+
+/// Extensions tried in order for the textures found next to a model.
+pub const TEXTURE_EXTENSIONS: [&str; 6] = ["png", "tga", "jpg", "jpeg", "bmp", "tif"];
+
+// The first `<stem>_<kind>` texture next to the model that exists.
+fn texture_path(obj_path: &Path, kind: &str) -> Option<PathBuf> {
+    let stem = obj_path.file_stem().unwrap_or_default().to_string_lossy();
+    TEXTURE_EXTENSIONS
+        .iter()
+        .map(|extension| obj_path.with_file_name(format!("{}_{}.{}", stem, kind, extension)))
+        .find(|path| path.exists())
+}
+
+// A missing texture is fine, one that exists but can't be decoded is not.
+fn load_texture(obj_path: &Path, kind: &str) -> Result<Option<Texture>, ModelError> {
+    match texture_path(obj_path, kind) {
+        Some(path) => open_texture(&path).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Opens an image and flips it so that v grows upwards like in OBJ files.
+pub fn open_texture(path: &Path) -> Result<Texture, ModelError> {
+    match image::open(path) {
+        Ok(image) => Ok(Texture::new(image.flipv())),
+        Err(error) => Err(ModelError::Texture {
+            path: path.to_path_buf(),
+            error,
+        }),
+    }
+}
+
+pub fn parse_floats(
+    values: &[&str],
+    required: usize,
+    statement: &str,
+    line: usize,
+) -> Result<Vec<f32>, ModelError> {
+    if values.len() < required {
+        return Err(ModelError::MissingValues {
+            line,
+            statement: statement.to_string(),
+        });
+    }
+    values
+        .iter()
+        .map(|value| {
+            value.parse::<f32>().map_err(|_| ModelError::InvalidNumber {
+                line,
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+// Starts a new mesh at face `start`, reusing the current one if no faces
+// were added to it yet.
+fn start_mesh(meshes: &mut Vec<Mesh>, name: String, material: Option<usize>, start: usize) {
+    let mesh = Mesh {
+        name,
+        faces: start..start,
+        material,
     };
-    face_str.split("/").map(&parse_index).collect()
+    match meshes.last_mut() {
+        Some(last) if last.faces.is_empty() => *last = mesh,
+        _ => meshes.push(mesh),
+    }
+}
+
+// One corner of a face: a vertex index with optional texture and normal
+// indices, all 0-based.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct FaceCorner {
+    vertex: u32,
+    texture: Option<u32>,
+    normal: Option<u32>,
+}
+
+// Turns the corners of an OBJ face into triangles. Polygons with more than
+// three corners are triangulated using the vertex positions seen so far.
+// Everything but the indices is copied from `template`.
+fn build_faces(
+    corners: &[FaceCorner],
+    verts: &[Vertex3<f32>],
+    template: Face,
+    line: usize,
+) -> Result<Vec<Face>, ModelError> {
+    let has_texture = corners[0].texture.is_some();
+    let has_normal = corners[0].normal.is_some();
+    let consistent = corners
+        .iter()
+        .all(|corner| corner.texture.is_some() == has_texture && corner.normal.is_some() == has_normal);
+    if !consistent {
+        return Err(ModelError::MixedFaceFormats { line });
+    }
+
+    let positions: Vec<Vertex3<f32>> = corners.iter().map(|corner| verts[corner.vertex as usize]).collect();
+    let triangles = triangulate(&positions);
+
+    Ok(triangles
+        .iter()
+        .map(|triangle| {
+            let corner = |i: usize| corners[triangle[i]];
+            let textures = [corner(0).texture, corner(1).texture, corner(2).texture];
+            let normals = [corner(0).normal, corner(1).normal, corner(2).normal];
+            Face {
+                vertexes: [corner(0).vertex, corner(1).vertex, corner(2).vertex],
+                textures: if has_texture { Some(textures.map(|t| t.unwrap_or(0))) } else { None },
+                normals: if has_normal { Some(normals.map(|n| n.unwrap_or(0))) } else { None },
+                ..template
+            }
+        })
+        .collect())
+}
+
+// Accepts `v`, `v/vt`, `v//vn` and `v/vt/vn`. `counts` holds how many
+// vertices, texture coordinates and normals have been read so far, which is
+// what negative indices are relative to and what positive ones must stay in.
+fn parse_face_string(face_str: &str, counts: [usize; 3], line: usize) -> Result<FaceCorner, ModelError> {
+    let invalid = || ModelError::InvalidIndex {
+        line,
+        value: face_str.to_string(),
+    };
+    let parse_index = |string: &str, count: usize| {
+        let value: i64 = string.parse().map_err(|_| invalid())?;
+        // waveform object files start index at 1 rather than 0, and -1 is
+        // the most recently defined element
+        let index = match value {
+            0 => return Err(invalid()),
+            v if v > 0 => v - 1,
+            v => count as i64 + v,
+        };
+        if index < 0 || index >= count as i64 {
+            return Err(ModelError::IndexOutOfRange {
+                line,
+                value: string.to_string(),
+                count,
+            });
+        }
+        Ok(index as u32)
+    };
+    let mut parts = face_str.split('/');
+    let vertex = parse_index(parts.next().unwrap_or(""), counts[0])?;
+    let texture = match parts.next() {
+        None | Some("") => None,
+        Some(index) => Some(parse_index(index, counts[1])?),
+    };
+    let normal = match parts.next() {
+        None => None,
+        Some(index) => Some(parse_index(index, counts[2])?),
+    };
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(FaceCorner {
+        vertex,
+        texture,
+        normal,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{env, fs, process};
+
+    fn parse(obj: &str) -> Result<Model, ModelError> {
+        Model::from_reader(obj.as_bytes())
+    }
 
     #[test]
     fn parse_face_string_returns_correct_vector() {
         let str = "1/2/3";
-        let expected = vec![0, 1, 2];
-        let actual = parse_face_string(str);
-        assert!(actual == expected);
+        let expected = FaceCorner {
+            vertex: 0,
+            texture: Some(1),
+            normal: Some(2),
+        };
+        let actual = parse_face_string(str, [3, 3, 3], 1).ok();
+        assert!(actual == Some(expected));
+    }
+
+    #[test]
+    fn parse_face_string_handles_all_index_forms() {
+        let corner = |vertex, texture, normal| FaceCorner {
+            vertex,
+            texture,
+            normal,
+        };
+        let parse = |face_str| parse_face_string(face_str, [10, 10, 10], 1).ok();
+        assert!(parse("4") == Some(corner(3, None, None)));
+        assert!(parse("4/5") == Some(corner(3, Some(4), None)));
+        assert!(parse("4//6") == Some(corner(3, None, Some(5))));
+        assert!(parse("-1/-10/-2") == Some(corner(9, Some(0), Some(8))));
+        assert!(parse("0/1/1").is_none());
+        assert!(parse("a/1/1").is_none());
+        assert!(parse("1/2/3/4").is_none());
+    }
+
+    #[test]
+    fn parses_comments_blank_lines_and_single_spaces() {
+        let model = parse(
+            "# a triangle\n\
+             \n\
+             v 0 0 0\n\
+             v 1.0   0.0 0.0 # trailing comment\n\
+             v 0 1 0\n\
+             vt 0.5 0.25\n\
+             vt  0.5 0.25 0.0\n\
+             vn 0 0 1\n\
+             o triangle\n\
+             f 1/1/1 2/2/1 3/1/1\n",
+        )
+        .unwrap();
+        assert!(model.verts.len() == 3);
+        assert!(model.verts[1] == Vertex3::init(1.0, 0.0, 0.0));
+        assert!(model.textures[1] == Vertex3::init(0.5, 0.25, 0.0));
+        assert!(model.normals.len() == 1);
+        assert!(model.faces[0].textures == Some([0, 1, 0]));
+    }
+
+    #[test]
+    fn parses_faces_without_texture_or_normal_indices() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1 2 3\nf 1//1 2//1 3//1\n").unwrap();
+        assert!(model.faces[0].textures.is_none());
+        assert!(model.faces[1].textures.is_none());
+        assert!(model.faces[1].normals == Some([0, 0, 0]));
+        // the missing normals are generated after the ones from the file
+        assert!(model.faces[0].normals == Some([1, 2, 3]));
+        assert!(model.vertex_normal(&model.faces[0], 0) == Vertex3::init(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn quads_and_polygons_are_triangulated() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0.5 0.2 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\n\
+             f 1 2 5 3 4\n",
+        )
+        .unwrap();
+        assert!(model.faces.len() == 2 + 3);
+        // each corner keeps its own texture index
+        assert!(model.faces[0].vertexes == [0, 1, 2] && model.faces[0].textures == Some([0, 1, 2]));
+        assert!(model.faces[1].vertexes == [0, 2, 3] && model.faces[1].textures == Some([0, 2, 3]));
+        // the pentagon dents inwards at its third corner, which a fan
+        // around the first corner would get wrong
+        let area: f32 = model.faces[2..]
+            .iter()
+            .map(|face| Vertex3::cross(model.vertex(face, 1) - model.vertex(face, 0), model.vertex(face, 2) - model.vertex(face, 0)).z / 2.0)
+            .sum();
+        assert!((area - 0.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn resolves_relative_indices_against_elements_read_so_far() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvn 0 0 1\n\
+             f -3/-2/-1 -2/-1/-1 -1/-2/-1\n\
+             v 1 1 0\nvt 1 1\n\
+             f 2/2/1 -1/-1/-1 3/1/1\n",
+        )
+        .unwrap();
+        assert!(model.faces[0].vertexes == [0, 1, 2]);
+        assert!(model.faces[0].textures == Some([0, 1, 0]));
+        assert!(model.faces[0].normals == Some([0, 0, 0]));
+        // -1 now refers to the vertex and texture coordinate added after
+        // the first face, mixed freely with absolute indices
+        assert!(model.faces[1].vertexes == [1, 3, 2]);
+        assert!(model.faces[1].textures == Some([1, 2, 0]));
+    }
+
+    #[test]
+    fn reports_indices_out_of_range() {
+        match parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n") {
+            Err(ModelError::IndexOutOfRange { line: 4, ref value, count: 3 }) if value == "4" => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -4 -2 -1\n") {
+            Err(ModelError::IndexOutOfRange { line: 4, ref value, count: 3 }) if value == "-4" => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        // faces can't refer ahead to elements defined later in the file
+        match parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2/1 3/1\nvt 0 0\n") {
+            Err(ModelError::IndexOutOfRange { line: 4, count: 0, .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn parses_smoothing_groups() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\ns 4\nf 1 2 3\ns off\nf 1 2 3\ns 0\nf 1 2 3\n").unwrap();
+        let groups: Vec<Option<u32>> = model.faces.iter().map(|face| face.smoothing_group).collect();
+        assert!(groups == vec![Some(0), Some(4), None, None]);
+        match parse("s smooth\n") {
+            Err(ModelError::InvalidNumber { line: 1, .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        match parse("v 0 0 0\nv 1 x 0\n") {
+            Err(ModelError::InvalidNumber { line: 2, ref value }) if value == "x" => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("v 0 0\n") {
+            Err(ModelError::MissingValues { line: 1, .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("v 0 0 0\n\nf 1 2\n") {
+            Err(ModelError::MissingValues { line: 3, .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("v 0 0 0\nvt 0 0\nf 1/1 1 1\n") {
+            Err(ModelError::MixedFaceFormats { line: 3 }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("v 0 0 0\nf 1 0 1\n") {
+            Err(ModelError::InvalidIndex { line: 2, .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn faces_are_tagged_with_their_material() {
+        let model = parse(
+            "mtllib scene.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             f 1 2 3\n\
+             usemtl red\nf 1 2 3\n\
+             usemtl blue\nf 1 2 3\n\
+             usemtl red\nf 1 2 3\n",
+        )
+        .unwrap();
+        assert!(model.materials.len() == 2);
+        let materials: Vec<Option<usize>> = model.faces.iter().map(|face| face.material).collect();
+        assert!(materials == vec![None, Some(0), Some(1), Some(0)]);
+        assert!(model.material(&model.faces[2]).unwrap().name == "blue");
+    }
+
+    #[test]
+    fn groups_and_materials_split_the_model_into_meshes() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+             f 1 2 3\n\
+             o head\n\
+             usemtl skin\nf 1 2 3\nf 2 4 3\n\
+             g left eye\nusemtl eye\nf 1 2 3\n\
+             usemtl skin\nf 1 2 4 3\n\
+             g\n",
+        )
+        .unwrap();
+        let mesh = |name: &str, faces: Range<usize>, material| Mesh {
+            name: name.to_string(),
+            faces,
+            material,
+        };
+        assert!(
+            model.meshes
+                == vec![
+                    mesh("default", 0..1, None),
+                    mesh("head", 1..3, Some(0)),
+                    mesh("left eye", 3..4, Some(1)),
+                    mesh("left eye", 4..6, Some(0)),
+                ]
+        );
+        let faces: usize = model.meshes_named("left eye").map(|mesh| mesh.faces_len()).sum();
+        assert!(faces == 3);
+        assert!(model.meshes_named("ear").next().is_none());
+    }
+
+    #[test]
+    fn library_definitions_replace_used_materials() {
+        let mut model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n").unwrap();
+        let mtl = "newmtl unused\nKd 0 0 1\nnewmtl red\nKd 1 0 0\n";
+        model.add_materials(Material::parse_library(mtl.as_bytes(), Path::new("")).unwrap());
+        assert!(model.materials.len() == 2);
+        let face = model.faces[0];
+        assert!(model.material(&face).unwrap().diffuse == Vertex3::init(1.0, 0.0, 0.0));
+        assert!(model.diffuse(&face, Vertex3::new(), Derivatives::zero()) == Rgba([1.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn normalize_transform_centers_the_model_in_the_unit_sphere() {
+        let model = parse("v 10 10 10\nv 14 10 10\nv 10 13 10\nf 1 2 3\n").unwrap();
+        let bounds = model.bounding_box().unwrap();
+        assert!(bounds.min == Vertex3::init(10.0, 10.0, 10.0) && bounds.max == Vertex3::init(14.0, 13.0, 10.0));
+        // the far corners of the box are 2.5 from its center
+        assert!(model.bounding_sphere().unwrap().radius == 2.5);
+
+        let transform = model.normalize_transform();
+        let moved = (transform * model.verts[1].to_homogeneous()).to_vector();
+        assert!((moved - Vertex3::init(0.8, -0.6, 0.0)).norm() < 1e-6);
+        // the model itself stays where it is
+        assert!(model.verts[1] == Vertex3::init(14.0, 10.0, 10.0));
+        assert!(Model::empty().bounding_box().is_none());
+    }
+
+    #[test]
+    fn load_finds_textures_in_other_formats() {
+        let dir = env::temp_dir().join(format!("pocket-renderer-tga-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5 0.5\nf 1/1 2/1 3/1\n").unwrap();
+        // an uncompressed 1x1 true color TGA holding one red pixel as BGR
+        let mut tga = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 24, 0];
+        tga.extend_from_slice(&[0, 0, 255]);
+        fs::write(dir.join("tri_diffuse.tga"), tga).unwrap();
+        let model = Model::load(&dir.join("tri.obj"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let model = model.unwrap();
+        let face = model.faces[0];
+        let uv = model.vertex_uv(&face, 0);
+        assert!(model.diffuse(&face, uv, Derivatives::zero()) == Rgba([1.0, 0.0, 0.0, 1.0]));
+        assert!(model.normal_map.is_none());
+    }
+
+    #[test]
+    fn load_reports_missing_files() {
+        match Model::load(Path::new("models/does_not_exist.obj")) {
+            Err(ModelError::Open { .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
     }
 }
@@ -0,0 +1,200 @@
+use geometry::Vertex3;
+use model::Model;
+use std::collections::HashMap;
+use std::f32;
+use std::vec::Vec;
+
+/// How the faces around a vertex contribute to its normal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Weighting {
+    /// Larger faces pull the normal further towards their own.
+    Area,
+    /// Each face counts with the angle of its corner at the vertex, so the
+    /// result doesn't depend on how the surface was tessellated.
+    Angle,
+}
+
+/// Tuning for the vertex normals generated for faces without `vn` data.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NormalSettings {
+    pub weighting: Weighting,
+    /// Faces meeting at a sharper angle than this, in degrees, get separate
+    /// normals along their shared edge even within one smoothing group.
+    pub crease_angle: Option<f32>,
+}
+
+impl NormalSettings {
+    pub fn new() -> NormalSettings {
+        NormalSettings {
+            weighting: Weighting::Angle,
+            crease_angle: None,
+        }
+    }
+}
+
+impl Default for NormalSettings {
+    fn default() -> NormalSettings {
+        NormalSettings::new()
+    }
+}
+
+/// Gives every smoothed face without normals a normal per corner, averaged
+/// from the faces of the same smoothing group around that vertex. Faces
+/// with smoothing turned off keep falling back to their flat normal.
+pub fn generate(model: &mut Model, settings: NormalSettings) {
+    let targets: Vec<usize> = (0..model.faces.len())
+        .filter(|&f| model.faces[f].normals.is_none() && model.faces[f].smoothing_group.is_some())
+        .collect();
+    if targets.is_empty() {
+        return;
+    }
+
+    // the unit normal and area of every face, whether smoothed or not
+    let (normals, areas): (Vec<Vertex3<f32>>, Vec<f32>) = model
+        .faces
+        .iter()
+        .map(|face| {
+            let v0 = model.vertex(face, 0);
+            let n = Vertex3::cross(model.vertex(face, 1) - v0, model.vertex(face, 2) - v0);
+            let length = n.norm();
+            if length > 0.0 {
+                (n * (1.0 / length), length / 2.0)
+            } else {
+                (n, 0.0)
+            }
+        })
+        .unzip();
+
+    let mut around: HashMap<u32, Vec<usize>> = HashMap::new();
+    for &f in targets.iter() {
+        for &v in model.faces[f].vertexes.iter() {
+            around.entry(v).or_default().push(f);
+        }
+    }
+
+    let crease = settings.crease_angle.map(|degrees| degrees.to_radians().cos());
+    // identical normals at the same vertex are shared
+    let mut generated: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+    for &f in targets.iter() {
+        let face = model.faces[f];
+        let mut indices = [0; 3];
+        for (i, &v) in face.vertexes.iter().enumerate() {
+            let mut sum = Vertex3::new();
+            for &g in around[&v].iter() {
+                if model.faces[g].smoothing_group != face.smoothing_group {
+                    continue;
+                }
+                if let Some(cos) = crease {
+                    if normals[f] * normals[g] < cos {
+                        continue;
+                    }
+                }
+                let weight = match settings.weighting {
+                    Weighting::Area => areas[g],
+                    Weighting::Angle => corner_angle(model, g, v),
+                };
+                sum = sum + normals[g] * weight;
+            }
+            let normal = if sum.norm() > 0.0 { sum.normalize() } else { normals[f] };
+            let key = (v, [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()]);
+            let normals_len = model.normals.len() as u32;
+            indices[i] = *generated.entry(key).or_insert(normals_len);
+            if indices[i] == normals_len {
+                model.normals.push(normal);
+            }
+        }
+        model.faces[f].normals = Some(indices);
+    }
+}
+
+// The interior angle of face `f` at its corner on vertex `v`.
+fn corner_angle(model: &Model, f: usize, v: u32) -> f32 {
+    let face = &model.faces[f];
+    let i = match face.vertexes.iter().position(|&corner| corner == v) {
+        Some(i) => i,
+        None => return 0.0,
+    };
+    let p = model.vertex(face, i);
+    let a = model.vertex(face, (i + 1) % 3) - p;
+    let b = model.vertex(face, (i + 2) % 3) - p;
+    let lengths = a.norm() * b.norm();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+    (a * b / lengths).clamp(-1.0, 1.0).acos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(obj: &str) -> Model {
+        Model::from_reader(obj.as_bytes()).unwrap()
+    }
+
+    fn close(a: Vertex3<f32>, b: Vertex3<f32>) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    // two faces of a box meeting at a right angle along the edge 1-2
+    const CORNER: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 1 0 -1\nv 1 1 -1\n";
+
+    #[test]
+    fn normals_are_generated_when_missing() {
+        let model = parse(&format!("{}f 1 2 3 4\nf 2 5 6 3\n", CORNER));
+        let face = &model.faces[0];
+        assert!(face.normals.is_some());
+        // the shared edge gets the average of both faces
+        let shared = Vertex3::init(1.0, 0.0, 1.0).normalize();
+        assert!(close(model.vertex_normal(face, 1), shared));
+        assert!(close(model.vertex_normal(face, 0), Vertex3::init(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn smoothing_groups_keep_edges_hard() {
+        let model = parse(&format!("{}s 1\nf 1 2 3 4\ns 2\nf 2 5 6 3\ns off\nf 1 2 4\n", CORNER));
+        assert!(close(model.vertex_normal(&model.faces[0], 1), Vertex3::init(0.0, 0.0, 1.0)));
+        assert!(close(model.vertex_normal(&model.faces[2], 0), Vertex3::init(1.0, 0.0, 0.0)));
+        // faces with smoothing off use their flat normal
+        assert!(model.faces[4].normals.is_none());
+    }
+
+    #[test]
+    fn crease_angle_splits_sharp_edges() {
+        let mut model = parse(&format!("{}f 1 2 3 4\nf 2 5 6 3\n", CORNER));
+        for face in model.faces.iter_mut() {
+            face.normals = None;
+        }
+        let settings = NormalSettings {
+            crease_angle: Some(60.0),
+            ..NormalSettings::new()
+        };
+        generate(&mut model, settings);
+        assert!(close(model.vertex_normal(&model.faces[0], 1), Vertex3::init(0.0, 0.0, 1.0)));
+        assert!(close(model.vertex_normal(&model.faces[2], 0), Vertex3::init(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn weighting_changes_the_average() {
+        // a large face and a small one meeting at right angles, with right
+        // angled corners at their shared first vertex
+        let obj = "v 0 0 0\nv 4 0 0\nv 0 4 0\nv 0 0 1\nf 1 2 3\nf 1 4 2\n";
+        let angle = parse(obj);
+        // both corners at vertex 1 are right angles, so they count the same
+        let expected = Vertex3::init(0.0, 1.0, 1.0).normalize();
+        assert!(close(angle.vertex_normal(&angle.faces[0], 0), expected));
+
+        let mut area = parse(obj);
+        for face in area.faces.iter_mut() {
+            face.normals = None;
+        }
+        let settings = NormalSettings {
+            weighting: Weighting::Area,
+            ..NormalSettings::new()
+        };
+        generate(&mut area, settings);
+        // the first face has four times the area of the second
+        let expected = Vertex3::init(0.0, 1.0, 4.0).normalize();
+        assert!(close(area.vertex_normal(&area.faces[0], 0), expected));
+    }
+}
@@ -0,0 +1,488 @@
+use std::fs;
+use std::path::Path;
+use std::str;
+use std::vec::Vec;
+
+use geometry::Vertex3;
+use material::Material;
+use model::{Face, Mesh, Model, ModelError};
+use normals::NormalSettings;
+use triangulate::triangulate;
+
+/// Loads an ASCII or binary PLY file, generating the normals it lacks with
+/// the given settings.
+pub fn load(path: &Path, normal_settings: NormalSettings) -> Result<Model, ModelError> {
+    let data = fs::read(path).map_err(|error| ModelError::Open {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    from_slice(&data, normal_settings)
+}
+
+/// Builds a model from PLY data in any of the three encodings.
+///
+/// Vertices may carry normals (`nx`, `ny`, `nz`), texture coordinates
+/// (`u`, `v` or `s`, `t`) and colors (`red`, `green`, `blue`), which end up
+/// in `Model::colors`. Faces are read from the `vertex_indices` list and
+/// triangulated, and tagged with the material their `material_index`
+/// names, see `export::write_ply`. Elements other than `vertex` and `face`
+/// are skipped.
+pub fn from_slice(data: &[u8], normal_settings: NormalSettings) -> Result<Model, ModelError> {
+    let (header, body) = parse_header(data)?;
+    let tokens = match header.encoding {
+        Encoding::Ascii => {
+            let text = str::from_utf8(body).map_err(|_| malformed("ASCII data is not valid text"))?;
+            Some(text.split_whitespace())
+        }
+        _ => None,
+    };
+    let mut values = Values {
+        encoding: header.encoding,
+        data: body,
+        position: 0,
+        tokens,
+    };
+
+    let mut model = Model::empty();
+    model.materials = header.materials.iter().map(|name| Material::new(name)).collect();
+    // the corners of each face and its material
+    let mut polygons: Vec<(Vec<u32>, Option<usize>)> = Vec::new();
+    let mut has_normals = false;
+    let mut has_textures = false;
+    for element in header.elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_str()));
+                let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+                let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+                let uv = [find(&["u", "s", "texture_u"]), find(&["v", "t", "texture_v"])];
+                let color = [
+                    find(&["red", "diffuse_red"]),
+                    find(&["green", "diffuse_green"]),
+                    find(&["blue", "diffuse_blue"]),
+                ];
+                has_normals = normal.iter().all(Option::is_some);
+                has_textures = uv.iter().all(Option::is_some);
+                let has_colors = color.iter().all(Option::is_some);
+
+                let mut row = vec![0.0; element.properties.len()];
+                for _ in 0..element.count {
+                    for (value, property) in row.iter_mut().zip(element.properties.iter()) {
+                        *value = match property.kind {
+                            Kind::Scalar(scalar) => values.read(scalar)?,
+                            // lists on vertices are of no use here
+                            Kind::List(..) => {
+                                values.skip(property.kind)?;
+                                0.0
+                            }
+                        };
+                    }
+                    let get = |index: Option<usize>| index.map(|i| row[i] as f32).unwrap_or(0.0);
+                    model.verts.push(Vertex3::init(get(position[0]), get(position[1]), get(position[2])));
+                    if has_normals {
+                        model.normals.push(Vertex3::init(get(normal[0]), get(normal[1]), get(normal[2])));
+                    }
+                    if has_textures {
+                        model.textures.push(Vertex3::init(get(uv[0]), get(uv[1]), 0.0));
+                    }
+                    if has_colors {
+                        let channel = |index: Option<usize>| {
+                            let i = index.unwrap();
+                            row[i] as f32 / element.properties[i].kind.color_scale()
+                        };
+                        model.colors.push(Vertex3::init(channel(color[0]), channel(color[1]), channel(color[2])));
+                    }
+                }
+            }
+            "face" => {
+                let indices = element
+                    .properties
+                    .iter()
+                    .position(|p| p.name == "vertex_indices" || p.name == "vertex_index");
+                let material = element.properties.iter().position(|p| p.name == "material_index");
+                for _ in 0..element.count {
+                    let mut polygon = Vec::new();
+                    let mut face_material = None;
+                    for (i, property) in element.properties.iter().enumerate() {
+                        if Some(i) == material {
+                            let value = values.read(match property.kind {
+                                Kind::Scalar(scalar) => scalar,
+                                Kind::List(..) => return Err(malformed("`material_index` must not be a list")),
+                            })?;
+                            // -1 stands for no material
+                            if value >= 0.0 {
+                                if value as usize >= header.materials.len() {
+                                    return Err(malformed(format!("material index {} is out of range", value)));
+                                }
+                                face_material = Some(value as usize);
+                            }
+                        } else if Some(i) == indices {
+                            let (count, item) = match property.kind {
+                                Kind::List(count, item) => (count, item),
+                                Kind::Scalar(_) => return Err(malformed("`vertex_indices` must be a list")),
+                            };
+                            for _ in 0..values.read(count)? as usize {
+                                let index = values.read(item)?;
+                                if index < 0.0 {
+                                    return Err(malformed(format!("negative vertex index {}", index)));
+                                }
+                                polygon.push(index as u32);
+                            }
+                        } else {
+                            values.skip(property.kind)?;
+                        }
+                    }
+                    polygons.push((polygon, face_material));
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for property in element.properties.iter() {
+                        values.skip(property.kind)?;
+                    }
+                }
+            }
+        }
+    }
+
+    for &(ref polygon, material) in polygons.iter() {
+        if let Some(&index) = polygon.iter().find(|&&index| index as usize >= model.verts.len()) {
+            return Err(malformed(format!(
+                "vertex index {} is out of range, only {} vertices",
+                index,
+                model.verts.len()
+            )));
+        }
+        let corners: Vec<Vertex3<f32>> = polygon.iter().map(|&index| model.verts[index as usize]).collect();
+        for triangle in triangulate(&corners) {
+            let vertexes = triangle.map(|i| polygon[i]);
+            model.faces.push(Face {
+                vertexes,
+                textures: if has_textures { Some(vertexes) } else { None },
+                normals: if has_normals { Some(vertexes) } else { None },
+                tangents: None,
+                material,
+                smoothing_group: Some(0),
+            });
+        }
+    }
+    model.meshes.push(Mesh {
+        name: "default".to_string(),
+        faces: 0..model.faces.len(),
+        material: None,
+    });
+    model.generate_normals(normal_settings);
+    model.generate_tangents();
+    Ok(model)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    Scalar(Scalar),
+    /// The type of the item count, then of the items.
+    List(Scalar, Scalar),
+}
+
+impl Kind {
+    // What a color channel of this type is divided by to land in [0, 1].
+    fn color_scale(self) -> f32 {
+        match self {
+            Kind::Scalar(Scalar::U8) => 255.0,
+            Kind::Scalar(Scalar::U16) => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    kind: Kind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+    /// Names from `comment material` lines, which `material_index` refers to.
+    materials: Vec<String>,
+}
+
+// Returns the header and the data that follows it.
+fn parse_header(data: &[u8]) -> Result<(Header, &[u8]), ModelError> {
+    if !data.starts_with(b"ply") {
+        return Err(malformed("missing `ply` magic number"));
+    }
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut materials: Vec<String> = Vec::new();
+    let mut position = 0;
+    loop {
+        let end = match data[position..].iter().position(|&b| b == b'\n') {
+            Some(end) => position + end,
+            None => return Err(malformed("missing `end_header`")),
+        };
+        let line = str::from_utf8(&data[position..end]).map_err(|_| malformed("header is not valid text"))?;
+        position = end + 1;
+        let values: Vec<&str> = line.split_whitespace().collect();
+        match values.first().cloned() {
+            Some("format") => {
+                encoding = match values.get(1).cloned() {
+                    Some("ascii") => Some(Encoding::Ascii),
+                    Some("binary_little_endian") => Some(Encoding::LittleEndian),
+                    Some("binary_big_endian") => Some(Encoding::BigEndian),
+                    _ => return Err(malformed(format!("unknown format `{}`", line))),
+                }
+            }
+            Some("element") => {
+                let count = values.get(2).and_then(|count| count.parse().ok());
+                match (values.get(1), count) {
+                    (Some(name), Some(count)) => elements.push(Element {
+                        name: name.to_string(),
+                        count,
+                        properties: Vec::new(),
+                    }),
+                    _ => return Err(malformed(format!("invalid element `{}`", line))),
+                }
+            }
+            Some("property") => {
+                let scalar = |index: usize| values.get(index).and_then(|name| Scalar::parse(name));
+                let property = if values.get(1) == Some(&"list") {
+                    match (scalar(2), scalar(3), values.get(4)) {
+                        (Some(count), Some(item), Some(name)) => Some((name, Kind::List(count, item))),
+                        _ => None,
+                    }
+                } else {
+                    match (scalar(1), values.get(2)) {
+                        (Some(scalar), Some(name)) => Some((name, Kind::Scalar(scalar))),
+                        _ => None,
+                    }
+                };
+                match (property, elements.last_mut()) {
+                    (Some((name, kind)), Some(element)) => element.properties.push(Property {
+                        name: name.to_string(),
+                        kind,
+                    }),
+                    _ => return Err(malformed(format!("invalid property `{}`", line))),
+                }
+            }
+            Some("comment") if values.get(1) == Some(&"material") && values.len() > 2 => {
+                materials.push(values[2..].join(" "));
+            }
+            Some("end_header") => break,
+            // the magic number, comments and obj_info
+            _ => {}
+        }
+    }
+    match encoding {
+        Some(encoding) => Ok((
+            Header {
+                encoding,
+                elements,
+                materials,
+            },
+            &data[position..],
+        )),
+        None => Err(malformed("missing `format`")),
+    }
+}
+
+// Reads the values after the header one at a time.
+struct Values<'a> {
+    encoding: Encoding,
+    data: &'a [u8],
+    position: usize,
+    tokens: Option<str::SplitWhitespace<'a>>,
+}
+
+impl<'a> Values<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, ModelError> {
+        if let Some(ref mut tokens) = self.tokens {
+            let token = tokens.next().ok_or_else(|| malformed("data ends early"))?;
+            return token
+                .parse()
+                .map_err(|_| malformed(format!("`{}` is not a number", token)));
+        }
+        let size = scalar.size();
+        if self.position + size > self.data.len() {
+            return Err(malformed("data ends early"));
+        }
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+        self.position += size;
+        if self.encoding == Encoding::BigEndian {
+            bytes[..size].reverse();
+        }
+        let value = match scalar {
+            Scalar::I8 => bytes[0] as i8 as f64,
+            Scalar::U8 => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(bytes),
+        };
+        Ok(value)
+    }
+
+    fn skip(&mut self, kind: Kind) -> Result<(), ModelError> {
+        match kind {
+            Kind::Scalar(scalar) => {
+                self.read(scalar)?;
+            }
+            Kind::List(count, item) => {
+                let count = self.read(count)? as usize;
+                for _ in 0..count {
+                    self.read(item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn malformed<S: Into<String>>(message: S) -> ModelError {
+    ModelError::Malformed {
+        format: "PLY",
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Result<Model, ModelError> {
+        from_slice(data, NormalSettings::new())
+    }
+
+    const HEADER: &str = "element vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 1\nproperty list uchar int vertex_indices\n\
+        end_header\n";
+
+    fn check_square(model: &Model) {
+        // the quad is split into two triangles with generated normals
+        assert!(model.faces.len() == 2);
+        assert!(model.vertex(&model.faces[0], 1) == Vertex3::init(1.0, 0.0, 0.0));
+        assert!(model.vertex_normal(&model.faces[1], 2) == Vertex3::init(0.0, 0.0, 1.0));
+        assert!(model.vertex_color(&model.faces[0], 0) == Some(Vertex3::init(1.0, 0.0, 0.0)));
+        assert!(model.vertex_color(&model.faces[0], 2) == Some(Vertex3::init(0.0, 0.0, 1.0)));
+        assert!(model.meshes[0].faces == (0..2));
+    }
+
+    #[test]
+    fn loads_ascii_ply_with_colors() {
+        let data = format!(
+            "ply\nformat ascii 1.0\ncomment a colored square\n{}\
+             0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n\
+             4 0 1 2 3\n",
+            HEADER
+        );
+        check_square(&parse(data.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn loads_binary_ply_in_either_byte_order() {
+        let corners = [[0.0f32, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let colors = [[255u8, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+        for &(format, big) in [("binary_little_endian", false), ("binary_big_endian", true)].iter() {
+            let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+            let float = |f: f32| if big { f.to_be_bytes() } else { f.to_le_bytes() };
+            for (corner, color) in corners.iter().zip(colors.iter()) {
+                data.extend_from_slice(&float(corner[0]));
+                data.extend_from_slice(&float(corner[1]));
+                data.extend_from_slice(&float(0.0));
+                data.extend_from_slice(color);
+            }
+            data.push(4);
+            for index in 0..4i32 {
+                data.extend_from_slice(&if big { index.to_be_bytes() } else { index.to_le_bytes() });
+            }
+            check_square(&parse(&data).unwrap());
+        }
+    }
+
+    #[test]
+    fn reads_normals_and_texture_coordinates() {
+        let model = parse(
+            b"ply\nformat ascii 1.0\nelement vertex 3\n\
+              property double x\nproperty double y\nproperty double z\n\
+              property float nx\nproperty float ny\nproperty float nz\nproperty float s\nproperty float t\n\
+              element edge 1\nproperty int vertex1\nproperty int vertex2\n\
+              element face 1\nproperty uchar flags\nproperty list uchar uint vertex_index\nend_header\n\
+              0 0 0 0 1 0 0 0\n1 0 0 0 1 0 1 0\n0 1 0 0 1 0 0 1\n\
+              0 1\n\
+              7 3 0 1 2\n",
+        )
+        .unwrap();
+        let face = &model.faces[0];
+        assert!(model.colors.is_empty());
+        assert!(model.vertex_normal(face, 0) == Vertex3::init(0.0, 1.0, 0.0));
+        assert!(model.vertex_uv(face, 2) == Vertex3::init(0.0, 1.0, 0.0));
+        assert!(face.tangents.is_some());
+    }
+
+    #[test]
+    fn reports_malformed_data() {
+        let truncated = format!("ply\nformat ascii 1.0\n{}0 0 0 1 1 1\n", HEADER);
+        let out_of_range = format!("ply\nformat ascii 1.0\n{}0 0 0 1 1 1\n", HEADER.replace("vertex 4", "vertex 1"))
+            + "3 0 1 2\n";
+        for data in [truncated, out_of_range, "ply\nend_header\n".to_string(), "obj".to_string()].iter() {
+            match parse(data.as_bytes()) {
+                Err(ModelError::Malformed { format: "PLY", .. }) => {}
+                other => panic!("unexpected {:?}", other.err()),
+            }
+        }
+    }
+}
@@ -1,22 +1,361 @@
 extern crate image;
 
-use geometry::{Matrix4, Vertex2, Vertex3};
-use model::{Face, Model};
+use bounds::BoundingSphere;
+use clipping::Frustum;
+use framebuffer::RenderTarget;
+use geometry::{Derivatives, Matrix4, Vertex2, Vertex3, Vertex4};
+use image::Rgba;
+use model::{Face, Mesh, Model};
 use sdl2::pixels::Color;
-use sdl2::rect::Point;
-use sdl2::render::WindowCanvas;
+use shadow::{ShadowMap, ShadowSettings, ShadowShader};
 use std::f32;
+use tiled;
+use vertex_buffer::TransformCache;
 
 pub trait Shader {
-    fn fragment(&self, bar: Vertex3<f32>, pixel: &mut Color) -> bool;
+    /// Shades the pixel at barycentric coordinates `bar`. `derivatives` are
+    /// those of `bar`, shared by the 2x2 quad of pixels this one belongs to.
+    /// Returning false discards the fragment, leaving the target as it was.
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool;
+
+    /// How the fragments are combined with the target.
+    fn blend(&self) -> Blend {
+        Blend::Replace
+    }
+}
+
+impl<S: Shader> Shader for &S {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool {
+        (*self).fragment(bar, derivatives, pixel)
+    }
+
+    fn blend(&self) -> Blend {
+        (*self).blend()
+    }
+}
+
+/// Shades a piece of a clipped triangle. The rasterizer hands out barycentric
+/// coordinates relative to the piece; `corners` maps them back onto the
+/// original triangle whose attributes the inner shader interpolates.
+pub struct ClippedShader<'a, S: Shader + 'a> {
+    pub inner: &'a S,
+    pub corners: [Vertex3<f32>; 3],
+}
+
+impl<'a, S: Shader> Shader for ClippedShader<'a, S> {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool {
+        let original = (self.corners[0] * bar.x) + (self.corners[1] * bar.y) + (self.corners[2] * bar.z);
+        self.inner.fragment(original, derivatives.interpolate(&self.corners), pixel)
+    }
+
+    fn blend(&self) -> Blend {
+        self.inner.blend()
+    }
+}
+
+/// Gives the faces of another shader a blend mode, and discards the
+/// fragments it makes that are less opaque than `alpha_cutoff`.
+pub struct AlphaShader<S: Shader> {
+    pub inner: S,
+    pub blend: Blend,
+    pub alpha_cutoff: Option<f32>,
+}
+
+impl<S: Shader> Shader for AlphaShader<S> {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool {
+        if !self.inner.fragment(bar, derivatives, pixel) {
+            return false;
+        }
+        match self.alpha_cutoff {
+            Some(cutoff) => pixel.a as f32 / 255.0 >= cutoff,
+            None => true,
+        }
+    }
+
+    fn blend(&self) -> Blend {
+        self.blend
+    }
+}
+
+pub const DEPTH: u32 = 255;
+
+/// Which shader `Scene::render` uses for each face.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shading {
+    Gourad,
+    NormalMapped(NormalSpace),
+}
+
+/// How attributes are interpolated across a triangle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    /// Linear in screen space. Cheaper, but textures warp under perspective.
+    Affine,
+    /// Linear in world space, corrected using 1/w of each corner.
+    Perspective,
+}
+
+/// How a fragment is combined with the pixel already in the target. Every
+/// mode but `Replace` leaves the depth buffer alone, so whatever is drawn
+/// later behind a blended surface still shows through it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Blend {
+    /// The fragment overwrites the pixel.
+    Replace,
+    /// The fragment is mixed in by its alpha.
+    Alpha,
+    /// The fragment, scaled by its alpha, is added to the pixel. Suits
+    /// glows and fire.
+    Additive,
+    /// The pixel is multiplied by the fragment, like tinted glass.
+    Multiply,
+    /// Like `Alpha`, for colors that are already multiplied by their alpha.
+    Premultiplied,
+}
+
+impl Blend {
+    /// Combines the fragment `source` with the pixel `dest` behind it.
+    pub fn apply(self, source: Color, dest: Color) -> Color {
+        let alpha = source.a as f32 / 255.0;
+        let mix = |s: u8, d: u8| {
+            let (s, d) = (s as f32, d as f32);
+            let c = match self {
+                Blend::Replace => s,
+                Blend::Alpha => s * alpha + d * (1.0 - alpha),
+                Blend::Additive => d + s * alpha,
+                Blend::Multiply => s * d / 255.0,
+                Blend::Premultiplied => s + d * (1.0 - alpha),
+            };
+            c.round().clamp(0.0, 255.0) as u8
+        };
+        match self {
+            Blend::Replace => source,
+            _ => Color::RGBA(mix(source.r, dest.r), mix(source.g, dest.g), mix(source.b, dest.b), dest.a),
+        }
+    }
+}
+
+/// Camera and lighting used to draw a model into a render target.
+pub struct Scene {
+    pub eye: Vertex3<f32>,
+    pub center: Vertex3<f32>,
+    pub up: Vertex3<f32>,
+    pub light_dir: Vertex3<f32>,
+    pub shading: Shading,
+    pub interpolation: Interpolation,
+    /// Distances from the eye to the near and far clipping planes.
+    pub near: f32,
+    pub far: f32,
+    /// Runs a depth pass from the light first when set.
+    pub shadows: Option<ShadowSettings>,
+    /// Rasterizes screen tiles in parallel on this many threads when above 1.
+    pub threads: usize,
+    /// How faces that aren't fully opaque are combined with what is behind
+    /// them. They are drawn after every opaque face, from back to front.
+    pub blend: Blend,
+    /// Discards fragments less opaque than this, for cut-outs like leaves.
+    pub alpha_cutoff: Option<f32>,
+    /// Magnifies the view around `center`, see `frame`.
+    pub zoom: f32,
+}
+
+impl Scene {
+    pub fn new(eye: Vertex3<f32>) -> Scene {
+        Scene {
+            eye,
+            center: Vertex3::new(),
+            up: Vertex3::init(0.0, 1.0, 0.0),
+            light_dir: Vertex3::init(1.0, 1.0, 1.0),
+            shading: Shading::Gourad,
+            interpolation: Interpolation::Perspective,
+            near: 0.1,
+            far: 100.0,
+            shadows: None,
+            threads: 1,
+            blend: Blend::Alpha,
+            alpha_cutoff: None,
+            zoom: 1.0,
+        }
+    }
+
+    /// Aims the camera at the middle of the sphere and zooms until the
+    /// sphere fills the view. The eye keeps its direction from `center`, and
+    /// its distance, `near` and `far` are taken as multiples of the radius so
+    /// the perspective and the clipping are the same for a model of any
+    /// size. The model itself is left where it is.
+    ///
+    /// `zoom` holds one over the radius of the last sphere framed, which is
+    /// divided out first, so framing again starts over from the camera as
+    /// it was set up for the unit sphere.
+    pub fn frame(&mut self, sphere: BoundingSphere) {
+        let radius = if sphere.radius > 0.0 { sphere.radius } else { 1.0 };
+        let scale = radius * self.zoom;
+        self.eye = sphere.center + (self.eye - self.center) * scale;
+        self.center = sphere.center;
+        self.near *= scale;
+        self.far *= scale;
+        self.zoom = 1.0 / radius;
+    }
+
+    /// Draws every face of the model, returning the number of fragments
+    /// written to the target.
+    pub fn render<T: RenderTarget>(&self, model: &Model, target: &mut T) -> i32 {
+        let faces: Vec<usize> = (0..model.faces.len()).collect();
+        self.render_faces(model, &faces, target)
+    }
+
+    /// Draws only the faces of the given meshes of the model. Every mesh
+    /// still casts shadows.
+    pub fn render_meshes<T: RenderTarget>(&self, model: &Model, meshes: &[&Mesh], target: &mut T) -> i32 {
+        let faces: Vec<usize> = meshes.iter().flat_map(|mesh| mesh.faces.clone()).collect();
+        self.render_faces(model, &faces, target)
+    }
+
+    // The shadow settings with the scene's alpha cutoff filled in, unless
+    // they have one of their own.
+    fn shadow_settings(&self) -> Option<ShadowSettings> {
+        self.shadows.map(|settings| ShadowSettings {
+            alpha_cutoff: settings.alpha_cutoff.or(self.alpha_cutoff),
+            ..settings
+        })
+    }
+
+    // Draws the faces with the given indices.
+    fn render_faces<T: RenderTarget>(&self, model: &Model, faces: &[usize], target: &mut T) -> i32 {
+        let (width, height) = (target.width(), target.height());
+        if width == 0 || height == 0 {
+            return 0;
+        }
+        // keep the viewport square so the model isn't stretched on wide targets
+        let size = (width.min(height) * 3 / 4).max(1);
+        let viewport = viewport((width - size) / 2, (height - size) / 2, size, size, DEPTH);
+        let model_view = lookat(self.eye, self.center, self.up);
+        let projection = projection(self.eye, self.center);
+        // scaling x and y after the projection magnifies the image without
+        // changing its perspective
+        let mut zoom = Matrix4::identity();
+        zoom.set(0, 0, self.zoom);
+        zoom.set(1, 1, self.zoom);
+        let clip_from_view = viewport * zoom * projection;
+        let frustum = Frustum::new(
+            width,
+            height,
+            &clip_from_view,
+            (self.eye - self.center).norm(),
+            self.near,
+            self.far,
+        );
+        let transform = clip_from_view * model_view;
+        let light_dir = self.light_dir.normalize();
+        let shadow_map = self
+            .shadow_settings()
+            .map(|settings| ShadowMap::new(model, light_dir, self.up, width, height, settings));
+
+        // every vertex is transformed once, however many faces share it
+        let buffer = model.vertex_buffer();
+        let cache = TransformCache::new(buffer, &transform);
+
+        // opaque faces go first in their own order, then the others from the
+        // farthest to the nearest so each blends over what is behind it
+        let (mut faces, transparent): (Vec<usize>, Vec<usize>) =
+            faces.iter().partition(|&&f| !model.is_transparent(&model.faces[f]));
+        let transparent_coords: Vec<[Vertex4<f32>; 3]> =
+            transparent.iter().map(|&f| cache.triangle(buffer.triangles[f])).collect();
+        faces.extend(back_to_front(&transparent_coords).into_iter().map(|i| transparent[i]));
+
+        let clip_coords: Vec<[Vertex4<f32>; 3]> = faces.iter().map(|&f| cache.triangle(buffer.triangles[f])).collect();
+        let faces: Vec<&Face> = faces.iter().map(|&f| &model.faces[f]).collect();
+        let frame = Frame {
+            faces: &faces,
+            frustum: &frustum,
+            clip_coords: &clip_coords,
+            shadow_map: shadow_map.as_ref(),
+        };
+
+        match self.shading {
+            Shading::Gourad => {
+                self.draw_faces(model, &frame, |face| GouradShader::new(model, face, light_dir), target)
+            }
+            Shading::NormalMapped(space) => self.draw_faces(
+                model,
+                &frame,
+                |face| NormalMapShader::new(model, face, light_dir, space),
+                target,
+            ),
+        }
+    }
+
+    // Builds a shader for every face, blended when the face isn't opaque and
+    // wrapped in a shadow lookup when the frame has a shadow map, and submits
+    // them for rasterization.
+    fn draw_faces<'a, S, F, T>(
+        &self,
+        model: &'a Model,
+        frame: &Frame<'a>,
+        make_shader: F,
+        target: &mut T,
+    ) -> i32
+    where
+        S: Shader + Sync,
+        F: Fn(&'a Face) -> S,
+        T: RenderTarget,
+    {
+        let make_shader = |face: &'a Face| AlphaShader {
+            inner: make_shader(face),
+            blend: if model.is_transparent(face) { self.blend } else { Blend::Replace },
+            alpha_cutoff: self.alpha_cutoff,
+        };
+        let faces = frame.faces.iter().zip(frame.clip_coords.iter());
+        match frame.shadow_map {
+            Some(map) => {
+                let triangles = faces.map(|(&face, &coords)| {
+                    let mut world_coords = [Vertex3::new(); 3];
+                    for (i, world) in world_coords.iter_mut().enumerate() {
+                        *world = model.verts[face.get_vertex(i) as usize];
+                    }
+                    (coords, ShadowShader::new(make_shader(face), map, world_coords))
+                });
+                self.submit(frame.frustum, triangles, target)
+            }
+            None => {
+                let triangles = faces.map(|(&face, &coords)| (coords, make_shader(face)));
+                self.submit(frame.frustum, triangles, target)
+            }
+        }
+    }
+
+    fn submit<S, I, T>(&self, frustum: &Frustum, triangles: I, target: &mut T) -> i32
+    where
+        S: Shader + Sync,
+        I: Iterator<Item = ([Vertex4<f32>; 3], S)>,
+        T: RenderTarget,
+    {
+        if self.threads > 1 {
+            let triangles: Vec<_> = triangles.collect();
+            tiled::render(frustum, &triangles, self.interpolation, self.threads, target)
+        } else {
+            let interpolation = self.interpolation;
+            triangles
+                .map(|(coords, shader)| clipped_triangle(frustum, &coords, interpolation, shader, target))
+                .sum()
+        }
+    }
 }
 
-pub struct Scene {}
+// Per-frame state shared by every face drawn by `Scene::render`.
+struct Frame<'a> {
+    faces: &'a [&'a Face],
+    frustum: &'a Frustum,
+    clip_coords: &'a [[Vertex4<f32>; 3]],
+    shadow_map: Option<&'a ShadowMap>,
+}
 
 pub struct GouradShader<'a> {
     varying_intensity: Vertex3<f32>,
     varying_uv: [Vertex3<f32>; 3],
+    varying_color: Option<[Vertex3<f32>; 3]>,
     model: &'a Model,
+    face: Face,
 }
 
 impl<'a> GouradShader<'a> {
@@ -24,34 +363,191 @@ impl<'a> GouradShader<'a> {
         let mut intensity: [f32; 3] = [1.0; 3];
         let mut textures = [Vertex3::new(); 3];
         for i in 0..3 {
-            let normal_idx = face.get_normal(i) as usize;
-            let texture_idx = face.get_texture(i) as usize;
-            let normal = *model.normals.get(normal_idx).unwrap();
-            textures[i] = *model.textures.get(texture_idx).unwrap();
+            let normal = model.vertex_normal(face, i);
+            textures[i] = model.vertex_uv(face, i);
             intensity[i] = 0f32.max(normal * light_dir);
         }
         GouradShader {
             varying_intensity: Vertex3::init(intensity[0], intensity[1], intensity[2]),
             varying_uv: textures,
+            varying_color: vertex_colors(model, face),
             model: model,
+            face: *face,
         }
     }
 }
 
 impl<'a> Shader for GouradShader<'a> {
-    fn fragment(&self, bar: Vertex3<f32>, pixel: &mut Color) -> bool {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool {
         let intensity = self.varying_intensity * bar;
         let uv = (self.varying_uv[0] * bar.x)
             + (self.varying_uv[1] * bar.y)
             + (self.varying_uv[2] * bar.z);
-        let texture_pixel = self.model.uv(uv);
-        pixel.r = (texture_pixel[0] as f32 * intensity) as u8;
-        pixel.g = (texture_pixel[1] as f32 * intensity) as u8;
-        pixel.b = (texture_pixel[2] as f32 * intensity) as u8;
+        let duv = derivatives.interpolate(&self.varying_uv);
+        let texture_pixel = surface_color(self.model, &self.face, self.varying_color, uv, duv, bar);
+        light(texture_pixel, intensity, pixel);
+        true
+    }
+}
+
+/// Where the normals stored in a normal map are expressed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalSpace {
+    /// The map stores normals directly in model space.
+    Object,
+    /// The map stores normals relative to the surface, in the frame built
+    /// from the interpolated normal and the direction of increasing u and v.
+    Tangent,
+}
+
+/// Per-pixel lighting using normals sampled from the model's normal map.
+/// Falls back to the interpolated vertex normal when the map is missing.
+pub struct NormalMapShader<'a> {
+    varying_uv: [Vertex3<f32>; 3],
+    varying_normal: [Vertex3<f32>; 3],
+    varying_pos: [Vertex3<f32>; 3],
+    varying_tangent: Option<[Vertex4<f32>; 3]>,
+    varying_color: Option<[Vertex3<f32>; 3]>,
+    light_dir: Vertex3<f32>,
+    space: NormalSpace,
+    model: &'a Model,
+    face: Face,
+}
+
+impl<'a> NormalMapShader<'a> {
+    pub fn new(
+        model: &'a Model,
+        face: &Face,
+        light_dir: Vertex3<f32>,
+        space: NormalSpace,
+    ) -> NormalMapShader<'a> {
+        let mut uvs = [Vertex3::new(); 3];
+        let mut normals = [Vertex3::new(); 3];
+        let mut positions = [Vertex3::new(); 3];
+        for i in 0..3 {
+            uvs[i] = model.vertex_uv(face, i);
+            normals[i] = model.vertex_normal(face, i);
+            positions[i] = model.vertex(face, i);
+        }
+        let tangents = face
+            .tangents
+            .map(|_| [0, 1, 2].map(|i| model.vertex_tangent(face, i).unwrap()));
+        NormalMapShader {
+            varying_uv: uvs,
+            varying_normal: normals,
+            varying_pos: positions,
+            varying_tangent: tangents,
+            varying_color: vertex_colors(model, face),
+            light_dir,
+            space,
+            model,
+            face: *face,
+        }
+    }
+
+    fn interpolate(values: &[Vertex3<f32>; 3], bar: Vertex3<f32>) -> Vertex3<f32> {
+        (values[0] * bar.x) + (values[1] * bar.y) + (values[2] * bar.z)
+    }
+
+    // Moves `n` from tangent space into model space. With tangents from the
+    // model this follows MikkTSpace: the interpolated tangent and normal are
+    // used as they are and the bitangent is rebuilt from them.
+    fn tangent_to_model(&self, bar: Vertex3<f32>, normal: Vertex3<f32>, n: Vertex3<f32>) -> Vertex3<f32> {
+        match self.varying_tangent {
+            Some(tangents) => {
+                let tangent = (tangents[0] * bar.x) + (tangents[1] * bar.y) + (tangents[2] * bar.z);
+                let sign = if tangents[0].w < 0.0 { -1.0 } else { 1.0 };
+                let normal = NormalMapShader::interpolate(&self.varying_normal, bar);
+                let t = Vertex3::init(tangent.x, tangent.y, tangent.z);
+                let bitangent = Vertex3::cross(normal, t) * sign;
+                (t * n.x + bitangent * n.y + normal * n.z).normalize()
+            }
+            None => self.triangle_tangent_to_model(normal, n),
+        }
+    }
+
+    // Solves for the tangent (direction of increasing u) and bitangent
+    // (increasing v) of the triangle, then moves `n` from that frame into
+    // model space.
+    fn triangle_tangent_to_model(&self, normal: Vertex3<f32>, n: Vertex3<f32>) -> Vertex3<f32> {
+        let p = &self.varying_pos;
+        let uv = &self.varying_uv;
+        // rows of the matrix [p1 - p0; p2 - p0; normal]
+        let a = p[1] - p[0];
+        let b = p[2] - p[0];
+        let c = normal;
+        let det = a * Vertex3::cross(b, c);
+        if det.abs() < f32::EPSILON {
+            return normal;
+        }
+        // the columns of the inverse are the cross products of the rows
+        let solve = |v: Vertex3<f32>| {
+            (Vertex3::cross(b, c) * v.x + Vertex3::cross(c, a) * v.y + Vertex3::cross(a, b) * v.z)
+                * (1.0 / det)
+        };
+        let tangent = solve(Vertex3::init(uv[1].x - uv[0].x, uv[2].x - uv[0].x, 0.0)).normalize();
+        let bitangent = solve(Vertex3::init(uv[1].y - uv[0].y, uv[2].y - uv[0].y, 0.0)).normalize();
+        (tangent * n.x + bitangent * n.y + normal * n.z).normalize()
+    }
+}
+
+impl<'a> Shader for NormalMapShader<'a> {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool {
+        let uv = NormalMapShader::interpolate(&self.varying_uv, bar);
+        let duv = derivatives.interpolate(&self.varying_uv);
+        let normal = NormalMapShader::interpolate(&self.varying_normal, bar).normalize();
+        let n = match self.space {
+            NormalSpace::Object => self.model.normal(uv, duv).unwrap_or(normal),
+            NormalSpace::Tangent => match self.model.tangent_normal(&self.face, uv, duv) {
+                Some(n) => self.tangent_to_model(bar, normal, n),
+                None => normal,
+            },
+        };
+        let intensity = 0f32.max(n * self.light_dir);
+        let texture_pixel = surface_color(self.model, &self.face, self.varying_color, uv, duv, bar);
+        light(texture_pixel, intensity, pixel);
         true
     }
 }
 
+// The vertex colors of a face without texture coordinates, if the model has
+// any.
+fn vertex_colors(model: &Model, face: &Face) -> Option<[Vertex3<f32>; 3]> {
+    if face.textures.is_some() || model.colors.is_empty() {
+        return None;
+    }
+    Some([0, 1, 2].map(|i| model.vertex_color(face, i).unwrap_or_else(|| Vertex3::init(1.0, 1.0, 1.0))))
+}
+
+// The unlit color of a face at `bar`: its interpolated vertex colors when
+// it has them, its diffuse texture or material otherwise.
+fn surface_color(
+    model: &Model,
+    face: &Face,
+    colors: Option<[Vertex3<f32>; 3]>,
+    uv: Vertex3<f32>,
+    duv: Derivatives,
+    bar: Vertex3<f32>,
+) -> Rgba<f32> {
+    match colors {
+        Some(colors) => {
+            let color = (colors[0] * bar.x) + (colors[1] * bar.y) + (colors[2] * bar.z);
+            Rgba([color.x, color.y, color.z, 1.0])
+        }
+        None => model.diffuse(face, uv, duv),
+    }
+}
+
+// Writes an unlit color scaled by the light falling on it into the pixel.
+// Alpha is kept as it is for blending.
+fn light(color: Rgba<f32>, intensity: f32, pixel: &mut Color) {
+    let channel = |c: f32| (c * intensity * 255.0).round().clamp(0.0, 255.0) as u8;
+    pixel.r = channel(color[0]);
+    pixel.g = channel(color[1]);
+    pixel.b = channel(color[2]);
+    pixel.a = (color[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+}
+
 pub fn lookat(eye: Vertex3<f32>, center: Vertex3<f32>, up: Vertex3<f32>) -> Matrix4 {
     let z = (eye - center).normalize();
     let x = Vertex3::cross(up, z).normalize();
@@ -61,8 +557,11 @@ pub fn lookat(eye: Vertex3<f32>, center: Vertex3<f32>, up: Vertex3<f32>) -> Matr
         result.set(0, i, *x.at(i as i32).unwrap());
         result.set(1, i, *y.at(i as i32).unwrap());
         result.set(2, i, *z.at(i as i32).unwrap());
-        result.set(i, 3, -center.at(i as i32).unwrap());
     }
+    // move the center to the origin before rotating, so it works for any center
+    result.set(0, 3, -(x * center));
+    result.set(1, 3, -(y * center));
+    result.set(2, 3, -(z * center));
     result
 }
 
@@ -72,10 +571,10 @@ pub fn projection(eye: Vertex3<f32>, center: Vertex3<f32>) -> Matrix4 {
     result
 }
 
-pub fn viewport(x: u32, y: u32, h: u32, w: u32, depth: u32) -> Matrix4 {
+pub fn viewport(x: u32, y: u32, w: u32, h: u32, depth: u32) -> Matrix4 {
     let mut m = Matrix4::identity();
-    m.set(0, 3, (x + w) as f32 / 2.0);
-    m.set(1, 3, (y + h) as f32 / 2.0);
+    m.set(0, 3, x as f32 + w as f32 / 2.0);
+    m.set(1, 3, y as f32 + h as f32 / 2.0);
     m.set(2, 3, depth as f32 / 2.0);
 
     m.set(0, 0, w as f32 / 2.0);
@@ -84,13 +583,99 @@ pub fn viewport(x: u32, y: u32, h: u32, w: u32, depth: u32) -> Matrix4 {
     m
 }
 
-pub fn triangle<S: Shader>(
+// The order to draw triangles in from the farthest to the nearest. Clip space
+// w grows with the distance from the eye and, unlike the depth after the
+// perspective divide, keeps its meaning for corners behind the eye.
+fn back_to_front(clip_coords: &[[Vertex4<f32>; 3]]) -> Vec<usize> {
+    let mut keys: Vec<(f32, usize)> = clip_coords
+        .iter()
+        .enumerate()
+        .map(|(i, coords)| (coords.iter().map(|v| v.w).sum(), i))
+        .collect();
+    keys.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keys.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Clips a triangle given in clip space (before the perspective divide)
+/// against the frustum, then rasterizes whatever is left of it.
+pub fn clipped_triangle<S: Shader, T: RenderTarget>(
+    frustum: &Frustum,
+    clip_coords: &[Vertex4<f32>; 3],
+    interpolation: Interpolation,
+    shader: S,
+    target: &mut T,
+) -> i32 {
+    // the common case needs neither clipping nor remapping
+    if clip_coords.iter().all(|&v| frustum.contains(v)) {
+        return rasterize(clip_coords, interpolation, shader, target);
+    }
+
+    let polygon = frustum.clip_triangle(clip_coords);
+    let mut render_count = 0;
+    // the clipped polygon is convex, so a fan around its first corner covers it
+    for i in 1..polygon.len().saturating_sub(1) {
+        let corners = [polygon[0], polygon[i], polygon[i + 1]];
+        let piece_coords = [corners[0].position, corners[1].position, corners[2].position];
+        let piece = ClippedShader {
+            inner: &shader,
+            corners: [corners[0].bar, corners[1].bar, corners[2].bar],
+        };
+        render_count += rasterize(&piece_coords, interpolation, piece, target);
+    }
+    render_count
+}
+
+/// Rasterizes a triangle whose corners are already in screen space.
+pub fn triangle<S: Shader, T: RenderTarget>(
     verts: &[Vertex3<f32>; 3],
     shader: S,
-    zbuffer: &mut [f32],
-    canvas: &mut WindowCanvas,
+    target: &mut T,
+) -> i32 {
+    let clip_coords = [
+        verts[0].to_homogeneous(),
+        verts[1].to_homogeneous(),
+        verts[2].to_homogeneous(),
+    ];
+    rasterize(&clip_coords, Interpolation::Affine, shader, target)
+}
+
+/// Rasterizes a triangle given in clip space. The perspective divide happens
+/// here so that 1/w of each corner is still around to correct the
+/// barycentric coordinates handed to the shader.
+pub fn rasterize<S: Shader, T: RenderTarget>(
+    clip_coords: &[Vertex4<f32>; 3],
+    interpolation: Interpolation,
+    shader: S,
+    target: &mut T,
+) -> i32 {
+    rasterize_at(clip_coords, interpolation, shader, target, (0, 0))
+}
+
+/// Like `rasterize`, for a target that only covers the part of the screen
+/// starting at `origin`. All the math is done in screen coordinates, so
+/// rendering a screen in pieces gives exactly the same pixels as rendering
+/// it at once.
+pub fn rasterize_at<S: Shader, T: RenderTarget>(
+    clip_coords: &[Vertex4<f32>; 3],
+    interpolation: Interpolation,
+    shader: S,
+    target: &mut T,
+    origin: (u32, u32),
 ) -> i32 {
-    let (height, width) = canvas.output_size().unwrap();
+    let verts = [
+        clip_coords[0].to_vector(),
+        clip_coords[1].to_vector(),
+        clip_coords[2].to_vector(),
+    ];
+    let inv_w = Vertex3::init(
+        1.0 / clip_coords[0].w,
+        1.0 / clip_coords[1].w,
+        1.0 / clip_coords[2].w,
+    );
+    let (width, height) = (target.width(), target.height());
+    if width == 0 || height == 0 {
+        return 0;
+    }
     let mut bboxmin = Vertex2::<f32> {
         x: f32::INFINITY,
         y: f32::INFINITY,
@@ -99,49 +684,416 @@ pub fn triangle<S: Shader>(
         x: f32::NEG_INFINITY,
         y: f32::NEG_INFINITY,
     };
+    let lower = Vertex2::<f32> {
+        x: origin.0 as f32,
+        y: origin.1 as f32,
+    };
     let clamp = Vertex2::<f32> {
-        x: width as f32,
-        y: height as f32,
+        x: (origin.0 + width - 1) as f32,
+        y: (origin.1 + height - 1) as f32,
     };
 
-    for i in 0..3 {
-        bboxmin.x = 0f32.max(bboxmin.x.min(verts[i].x));
-        bboxmax.x = clamp.x.min(bboxmax.x.max(verts[i].x));
-        bboxmin.y = 0f32.max(bboxmin.y.min(verts[i].y));
-        bboxmax.y = clamp.y.min(bboxmax.y.max(verts[i].y));
+    for vert in verts.iter() {
+        bboxmin.x = lower.x.max(bboxmin.x.min(vert.x));
+        bboxmax.x = clamp.x.min(bboxmax.x.max(vert.x));
+        bboxmin.y = lower.y.max(bboxmin.y.min(vert.y));
+        bboxmax.y = clamp.y.min(bboxmax.y.max(vert.y));
+    }
+
+    // the triangle lies entirely outside of the target
+    if bboxmin.x > bboxmax.x || bboxmin.y > bboxmax.y {
+        return 0;
     }
 
-    let mut p = Vertex3::<f32> {
-        x: bboxmin.x as f32,
-        y: bboxmin.y as f32,
-        z: 0.0,
+    let barycentric = |x: u32, y: u32| Vertex3::barycentric(verts[0], verts[1], verts[2], Vertex3::init(x as f32, y as f32, 0.0));
+    let correct = |bc_screen: Vertex3<f32>| match interpolation {
+        Interpolation::Affine => bc_screen,
+        Interpolation::Perspective => {
+            // attributes divided by w are linear in screen space
+            let bc = Vertex3::init(
+                bc_screen.x * inv_w.x,
+                bc_screen.y * inv_w.y,
+                bc_screen.z * inv_w.z,
+            );
+            bc * (1.0 / (bc.x + bc.y + bc.z))
+        }
     };
 
+    let blend = shader.blend();
+    let (min_x, min_y) = (bboxmin.x as u32, bboxmin.y as u32);
+    let (max_x, max_y) = (bboxmax.x as u32, bboxmax.y as u32);
     let mut render_count = 0;
 
-    for x in (bboxmin.x as u32)..(bboxmax.x as u32 + 1) {
-        for y in (bboxmin.y as u32)..(bboxmax.y as u32 + 1) {
-            p.x = x as f32;
-            p.y = y as f32;
-            let bc_screen = Vertex3::barycentric(verts[0], verts[1], verts[2], p);
-            if bc_screen.x < 0.0 || bc_screen.y < 0.0 || bc_screen.z < 0.0 {
-                continue;
-            }
-            p.z = 0.0;
-            p.z += verts[0].z * bc_screen.x;
-            p.z += verts[1].z * bc_screen.y;
-            p.z += verts[2].z * bc_screen.z;
-            let zbuff_idx = (p.x + p.y * (width as f32)) as usize;
-            if zbuffer[zbuff_idx - 1] <= p.z {
-                let mut pixel = Color::RGB(255u8, 255u8, 255u8);
-                shader.fragment(bc_screen, &mut pixel);
-                zbuffer[zbuff_idx - 1] = p.z;
-                canvas.set_draw_color(pixel);
-                canvas.draw_point(Point::new(p.x as i32, p.y as i32)).ok();
-                render_count += 1;
+    // pixels are visited in 2x2 quads lined up with the screen, like a GPU
+    // does, so the difference across each quad gives the derivatives
+    for quad_x in ((min_x & !1)..(max_x + 1)).step_by(2) {
+        for quad_y in ((min_y & !1)..(max_y + 1)).step_by(2) {
+            let mut derivatives = None;
+            for &(x, y) in [(quad_x, quad_y), (quad_x + 1, quad_y), (quad_x, quad_y + 1), (quad_x + 1, quad_y + 1)].iter() {
+                if x < min_x || x > max_x || y < min_y || y > max_y {
+                    continue;
+                }
+                let bc_screen = barycentric(x, y);
+                if bc_screen.x < 0.0 || bc_screen.y < 0.0 || bc_screen.z < 0.0 {
+                    continue;
+                }
+                let z = verts[0].z * bc_screen.x + verts[1].z * bc_screen.y + verts[2].z * bc_screen.z;
+                let (tx, ty) = (x - origin.0, y - origin.1);
+                if target.depth(tx, ty) <= z {
+                    // pixels of the quad outside the triangle still count
+                    let derivatives = *derivatives.get_or_insert_with(|| {
+                        let corner = correct(barycentric(quad_x, quad_y));
+                        Derivatives {
+                            dx: correct(barycentric(quad_x + 1, quad_y)) - corner,
+                            dy: correct(barycentric(quad_x, quad_y + 1)) - corner,
+                        }
+                    });
+                    let mut pixel = Color::RGB(255u8, 255u8, 255u8);
+                    if !shader.fragment(correct(bc_screen), derivatives, &mut pixel) {
+                        continue;
+                    }
+                    if blend == Blend::Replace {
+                        target.set_depth(tx, ty, z);
+                        target.set_pixel(tx, ty, pixel);
+                    } else {
+                        let behind = target.pixel(tx, ty);
+                        target.set_pixel(tx, ty, blend.apply(pixel, behind));
+                    }
+                    render_count += 1;
+                }
             }
         }
     }
 
     render_count
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use framebuffer::Framebuffer;
+    use image::Rgb;
+    use std::cell::Cell;
+    use texture::Texture;
+
+    fn quad_model() -> Model {
+        let mut model = Model::empty();
+        model.verts = vec![
+            Vertex3::init(0.0, 0.0, 0.0),
+            Vertex3::init(2.0, 0.0, 0.0),
+            Vertex3::init(0.0, 2.0, 0.0),
+        ];
+        model.textures = vec![
+            Vertex3::init(0.0, 0.0, 0.0),
+            Vertex3::init(1.0, 0.0, 0.0),
+            Vertex3::init(0.0, 1.0, 0.0),
+        ];
+        model.normals = vec![Vertex3::init(0.0, 0.0, 1.0); 3];
+        model.faces = vec![Face {
+            vertexes: [0, 1, 2],
+            textures: Some([0, 1, 2]),
+            normals: Some([0, 1, 2]),
+            tangents: None,
+            material: None,
+            smoothing_group: None,
+        }];
+        model.texture_image = Texture::from_color(Rgb([200, 200, 200]));
+        model
+    }
+
+    // writes the first barycentric coordinate into the red channel
+    struct BarycentricShader;
+
+    impl Shader for BarycentricShader {
+        fn fragment(&self, bar: Vertex3<f32>, _derivatives: Derivatives, pixel: &mut Color) -> bool {
+            *pixel = Color::RGB((bar.x * 255.0).round() as u8, 0, 0);
+            true
+        }
+    }
+
+    fn rasterize_receding_triangle(interpolation: Interpolation) -> Framebuffer {
+        let mut fb = Framebuffer::new(16, 16);
+        // the second corner is twice as far away as the others
+        let clip_coords = [
+            Vertex4::init(0.0, 0.0, 0.0, 1.0),
+            Vertex4::init(20.0, 0.0, 0.0, 2.0),
+            Vertex4::init(0.0, 10.0, 0.0, 1.0),
+        ];
+        rasterize(&clip_coords, interpolation, BarycentricShader, &mut fb);
+        fb
+    }
+
+    #[test]
+    fn affine_interpolation_is_linear_in_screen_space() {
+        let fb = rasterize_receding_triangle(Interpolation::Affine);
+        // halfway along the edge between the first two corners on screen
+        assert!(fb.get_pixel(5, 0).r == 128);
+    }
+
+    #[test]
+    fn perspective_interpolation_accounts_for_depth() {
+        let fb = rasterize_receding_triangle(Interpolation::Perspective);
+        // (0.5 / 1) / (0.5 / 1 + 0.5 / 2) = 2 / 3 of the way is still the near corner
+        assert!(fb.get_pixel(5, 0).r == 170);
+    }
+
+    // remembers the derivatives of the last fragment it shaded
+    struct DerivativeShader(Cell<Derivatives>);
+
+    impl Shader for DerivativeShader {
+        fn fragment(&self, _bar: Vertex3<f32>, derivatives: Derivatives, _pixel: &mut Color) -> bool {
+            self.0.set(derivatives);
+            true
+        }
+    }
+
+    #[test]
+    fn derivatives_follow_the_barycentric_coordinates() {
+        let verts = [
+            Vertex3::init(0.0, 0.0, 0.0),
+            Vertex3::init(8.0, 0.0, 0.0),
+            Vertex3::init(0.0, 8.0, 0.0),
+        ];
+        let shader = DerivativeShader(Cell::new(Derivatives::zero()));
+        let mut fb = Framebuffer::new(16, 16);
+        assert!(triangle(&verts, &shader, &mut fb) > 0);
+        // each step right moves an eighth of the way towards the second corner
+        let derivatives = shader.0.get();
+        assert!((derivatives.dx - Vertex3::init(-0.125, 0.125, 0.0)).norm() < 1e-6);
+        assert!((derivatives.dy - Vertex3::init(-0.125, 0.0, 0.125)).norm() < 1e-6);
+    }
+
+    // clips away the half of the triangle nearest its second corner
+    struct ClipPlaneShader;
+
+    impl Shader for ClipPlaneShader {
+        fn fragment(&self, bar: Vertex3<f32>, _derivatives: Derivatives, pixel: &mut Color) -> bool {
+            *pixel = Color::RGB(255, 255, 255);
+            bar.y < 0.5
+        }
+    }
+
+    #[test]
+    fn empty_targets_draw_nothing() {
+        let verts = [
+            Vertex3::init(0.0, 0.0, 0.0),
+            Vertex3::init(8.0, 0.0, 0.0),
+            Vertex3::init(0.0, 8.0, 0.0),
+        ];
+        assert!(triangle(&verts, BarycentricShader, &mut Framebuffer::new(0, 4)) == 0);
+        assert!(triangle(&verts, BarycentricShader, &mut Framebuffer::new(4, 0)) == 0);
+    }
+
+    #[test]
+    fn discarded_fragments_write_neither_color_nor_depth() {
+        let verts = [
+            Vertex3::init(0.0, 0.0, 1.0),
+            Vertex3::init(8.0, 0.0, 1.0),
+            Vertex3::init(0.0, 8.0, 1.0),
+        ];
+        let mut fb = Framebuffer::new(16, 16);
+        triangle(&verts, ClipPlaneShader, &mut fb);
+        assert!(fb.get_pixel(1, 1) == Color::RGB(255, 255, 255));
+        assert!(fb.depth(1, 1) == 1.0);
+        assert!(fb.get_pixel(6, 0) == Color::RGB(0, 0, 0));
+        assert!(fb.depth(6, 0) == f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn tangent_space_normal_follows_uv_directions() {
+        let model = quad_model();
+        let light_dir = Vertex3::init(0.0, 0.0, 1.0);
+        let shader = NormalMapShader::new(&model, &model.faces[0], light_dir, NormalSpace::Tangent);
+        let normal = Vertex3::init(0.0, 0.0, 1.0);
+        let bar = Vertex3::init(0.2, 0.3, 0.5);
+        assert!(shader.tangent_to_model(bar, normal, Vertex3::init(0.0, 0.0, 1.0)) == normal);
+        assert!(shader.tangent_to_model(bar, normal, Vertex3::init(1.0, 0.0, 0.0)) == Vertex3::init(1.0, 0.0, 0.0));
+        assert!(shader.tangent_to_model(bar, normal, Vertex3::init(0.0, 1.0, 0.0)) == Vertex3::init(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn tangent_space_uses_the_model_tangents() {
+        let mut model = quad_model();
+        model.generate_tangents();
+        assert!(model.faces[0].tangents.is_some());
+        // rotate the stored frame a quarter turn so it differs from the one
+        // the triangle would give
+        for tangent in model.tangents.iter_mut() {
+            *tangent = Vertex4::init(0.0, 1.0, 0.0, -1.0);
+        }
+        let light_dir = Vertex3::init(0.0, 0.0, 1.0);
+        let shader = NormalMapShader::new(&model, &model.faces[0], light_dir, NormalSpace::Tangent);
+        let normal = Vertex3::init(0.0, 0.0, 1.0);
+        let bar = Vertex3::init(0.2, 0.3, 0.5);
+        let along_u = shader.tangent_to_model(bar, normal, Vertex3::init(1.0, 0.0, 0.0));
+        let along_v = shader.tangent_to_model(bar, normal, Vertex3::init(0.0, 1.0, 0.0));
+        assert!(along_u == Vertex3::init(0.0, 1.0, 0.0));
+        // -cross(normal, tangent)
+        assert!(along_v == Vertex3::init(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn normal_map_shader_falls_back_to_vertex_normals() {
+        let model = quad_model();
+        let light_dir = Vertex3::init(0.0, 0.0, 1.0);
+        let shader = NormalMapShader::new(&model, &model.faces[0], light_dir, NormalSpace::Object);
+        let mut pixel = Color::RGB(0, 0, 0);
+        shader.fragment(Vertex3::init(0.3, 0.3, 0.4), Derivatives::zero(), &mut pixel);
+        assert!(pixel == Color::RGB(200, 200, 200));
+    }
+
+    #[test]
+    fn blend_modes() {
+        let source = Color::RGBA(200, 100, 0, 128);
+        let dest = Color::RGB(100, 100, 100);
+        assert!(Blend::Replace.apply(source, dest) == source);
+        assert!(Blend::Alpha.apply(source, dest) == Color::RGB(150, 100, 50));
+        assert!(Blend::Additive.apply(source, dest) == Color::RGB(200, 150, 100));
+        assert!(Blend::Multiply.apply(source, dest) == Color::RGB(78, 39, 0));
+        assert!(Blend::Premultiplied.apply(source, dest) == Color::RGB(250, 150, 50));
+    }
+
+    // a half transparent blue triangle in front of an opaque red one, listed
+    // first so it has to be moved after it
+    fn layered_model() -> Model {
+        let obj = "v -1 -1 0\nv 1 -1 0\nv 0 1 0\nv -1 -1 0.5\nv 1 -1 0.5\nv 0 1 0.5\nvn 0 0 1\n\
+                   usemtl glass\nf 4//1 5//1 6//1\nusemtl paint\nf 1//1 2//1 3//1\n";
+        let mut model = Model::from_reader(obj.as_bytes()).unwrap();
+        for material in model.materials.iter_mut() {
+            if material.name == "glass" {
+                material.diffuse = Vertex3::init(0.0, 0.0, 1.0);
+                material.dissolve = 0.5;
+            } else {
+                material.diffuse = Vertex3::init(1.0, 0.0, 0.0);
+            }
+        }
+        model
+    }
+
+    fn render_layers(threads: usize, alpha_cutoff: Option<f32>) -> Color {
+        let mut scene = Scene::new(Vertex3::init(0.0, 0.0, 3.0));
+        scene.light_dir = Vertex3::init(0.0, 0.0, 1.0);
+        scene.threads = threads;
+        scene.alpha_cutoff = alpha_cutoff;
+        let mut fb = Framebuffer::new(64, 64);
+        scene.render(&layered_model(), &mut fb);
+        fb.get_pixel(32, 32)
+    }
+
+    #[test]
+    fn triangles_crossing_the_eye_plane_sort_by_distance() {
+        let corner = |w: f32| Vertex4::init(0.0, 0.0, 1.0, w);
+        let near = [corner(1.5), corner(1.5), corner(1.5)];
+        // reaches behind the eye, where z / w flips sign, but is farther overall
+        let crossing = [corner(3.0), corner(3.0), corner(-0.5)];
+        let far = [corner(4.0), corner(4.0), corner(4.0)];
+        assert!(back_to_front(&[near, crossing, far]) == vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn transparent_faces_blend_over_opaque_ones() {
+        let model = layered_model();
+        assert!(model.is_transparent(&model.faces[0]));
+        assert!(!model.is_transparent(&model.faces[1]));
+        for threads in [1, 4].iter() {
+            let pixel = render_layers(*threads, None);
+            assert!(pixel.r == 127 && pixel.g == 0 && pixel.b == 128);
+        }
+    }
+
+    #[test]
+    fn transparent_faces_draw_from_back_to_front() {
+        let mut model = layered_model();
+        for material in model.materials.iter_mut() {
+            material.dissolve = 0.5;
+        }
+        let mut scene = Scene::new(Vertex3::init(0.0, 0.0, 3.0));
+        scene.light_dir = Vertex3::init(0.0, 0.0, 1.0);
+        let mut fb = Framebuffer::new(64, 64);
+        scene.render(&model, &mut fb);
+        // the far red face is blended first and the near blue one over it
+        let pixel = fb.get_pixel(32, 32);
+        assert!(pixel.r == 64 && pixel.b == 128);
+    }
+
+    #[test]
+    fn alpha_cutoff_discards_translucent_fragments() {
+        assert!(render_layers(1, Some(0.75)) == Color::RGB(255, 0, 0));
+    }
+
+    #[test]
+    fn lookat_moves_the_center_to_the_origin() {
+        let center = Vertex3::init(10.0, -4.0, 2.0);
+        let view = lookat(Vertex3::init(12.0, -3.0, 5.0), center, Vertex3::init(0.0, 1.0, 0.0));
+        let moved = (view * center.to_homogeneous()).to_vector();
+        assert!(moved.norm() < 1e-5);
+    }
+
+    #[test]
+    fn frame_fits_the_camera_around_the_model() {
+        // far from the origin and much larger than the default view
+        let obj = "v 80 80 100\nv 120 80 100\nv 100 120 100\nvn 0 0 1\nf 1//1 2//1 3//1\n";
+        let model = Model::from_reader(obj.as_bytes()).unwrap();
+        let mut scene = Scene::new(Vertex3::init(0.0, 0.0, 3.0));
+        scene.light_dir = Vertex3::init(0.0, 0.0, 1.0);
+        let mut fb = Framebuffer::new(64, 64);
+        assert!(scene.render(&model, &mut fb) == 0);
+
+        let sphere = model.bounding_sphere().unwrap();
+        scene.frame(sphere);
+        assert!(scene.center == sphere.center);
+        assert!(((scene.eye - sphere.center).norm() - 3.0 * sphere.radius).abs() < 1e-3);
+        let mut fb = Framebuffer::new(64, 64);
+        assert!(scene.render(&model, &mut fb) > 0);
+        assert!(fb.get_pixel(32, 32) != Color::RGB(0, 0, 0));
+        // nothing drawn reaches past the square viewport in the middle
+        assert!(fb.get_pixel(4, 32) == Color::RGB(0, 0, 0));
+    }
+
+    #[test]
+    fn framing_again_changes_nothing() {
+        let sphere = BoundingSphere {
+            center: Vertex3::init(5.0, 0.0, 0.0),
+            radius: 20.0,
+        };
+        let mut scene = Scene::new(Vertex3::init(1.0, 1.0, 3.0));
+        scene.frame(sphere);
+        let (eye, near, far, zoom) = (scene.eye, scene.near, scene.far, scene.zoom);
+        scene.frame(sphere);
+        assert!(scene.eye == eye && scene.near == near && scene.far == far && scene.zoom == zoom);
+
+        // a different sphere is framed as if it were the first
+        let other = BoundingSphere {
+            center: Vertex3::new(),
+            radius: 2.0,
+        };
+        scene.frame(other);
+        let mut fresh = Scene::new(Vertex3::init(1.0, 1.0, 3.0));
+        fresh.frame(other);
+        assert!((scene.eye - fresh.eye).norm() < 1e-4);
+        assert!((scene.near - fresh.near).abs() < 1e-6 && (scene.far - fresh.far).abs() < 1e-4);
+    }
+
+    #[test]
+    fn shadows_keep_their_own_alpha_cutoff() {
+        let mut scene = Scene::new(Vertex3::init(0.0, 0.0, 3.0));
+        scene.alpha_cutoff = Some(0.5);
+        scene.shadows = Some(ShadowSettings::new());
+        assert!(scene.shadow_settings().unwrap().alpha_cutoff == Some(0.5));
+        scene.shadows = Some(ShadowSettings {
+            alpha_cutoff: Some(0.25),
+            ..ShadowSettings::new()
+        });
+        assert!(scene.shadow_settings().unwrap().alpha_cutoff == Some(0.25));
+    }
+
+    #[test]
+    fn renders_a_single_pixel_target() {
+        let mut scene = Scene::new(Vertex3::init(0.0, 0.0, 3.0));
+        scene.shadows = Some(ShadowSettings::new());
+        let mut fb = Framebuffer::new(1, 1);
+        scene.render(&layered_model(), &mut fb);
+        assert!(fb.depth(0, 0) > f32::NEG_INFINITY);
+        assert!(scene.render(&layered_model(), &mut Framebuffer::new(0, 1)) == 0);
+    }
+}
@@ -0,0 +1,284 @@
+use framebuffer::Framebuffer;
+use geometry::{Derivatives, Matrix4, Vertex3};
+use model::{Face, Model};
+use renderer::{self, Blend, Shader, DEPTH};
+use sdl2::pixels::Color;
+use std::f32;
+use std::vec::Vec;
+use vertex_buffer::TransformCache;
+
+/// Tuning for the shadow pass.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowSettings {
+    /// Depth offset, in depth buffer units, subtracted before comparing a
+    /// fragment against the shadow map. Too small gives shadow acne, too
+    /// large detaches shadows from their casters.
+    pub bias: f32,
+    /// Radius of the percentage-closer filter. Zero takes a single sample,
+    /// 1 averages a 3x3 neighbourhood and so on.
+    pub pcf_radius: u32,
+    /// How much light is left in fully shadowed areas, from 0 to 1.
+    pub ambient: f32,
+    /// Light passes through the parts of faces that are less opaque than
+    /// this, so cut-outs cast shadows with holes in them. When None, `Scene`
+    /// uses its own `alpha_cutoff`.
+    pub alpha_cutoff: Option<f32>,
+}
+
+impl ShadowSettings {
+    pub fn new() -> ShadowSettings {
+        ShadowSettings {
+            bias: 5.0,
+            pcf_radius: 1,
+            ambient: 0.3,
+            alpha_cutoff: None,
+        }
+    }
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings::new()
+    }
+}
+
+/// Depth of the scene as seen from the light, along with the transform that
+/// takes model coordinates into the map.
+pub struct ShadowMap {
+    width: u32,
+    height: u32,
+    depth: Vec<f32>,
+    transform: Matrix4,
+    settings: ShadowSettings,
+}
+
+// Only the depth attachment matters in the light pass, so fragments are
+// only shaded to discard the see-through parts of cut-outs.
+struct DepthShader<'a> {
+    model: &'a Model,
+    face: Face,
+    varying_uv: [Vertex3<f32>; 3],
+    alpha_cutoff: Option<f32>,
+}
+
+impl<'a> Shader for DepthShader<'a> {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, _pixel: &mut Color) -> bool {
+        let cutoff = match self.alpha_cutoff {
+            Some(cutoff) => cutoff,
+            None => return true,
+        };
+        let uv = (self.varying_uv[0] * bar.x) + (self.varying_uv[1] * bar.y) + (self.varying_uv[2] * bar.z);
+        let duv = derivatives.interpolate(&self.varying_uv);
+        self.model.diffuse(&self.face, uv, duv)[3] >= cutoff
+    }
+}
+
+impl ShadowMap {
+    /// Renders the model from the direction of a directional light. The
+    /// light uses an orthographic projection, so only its direction matters,
+    /// and is fitted around the model so all of it casts shadows.
+    pub fn new(
+        model: &Model,
+        light_dir: Vertex3<f32>,
+        up: Vertex3<f32>,
+        width: u32,
+        height: u32,
+        settings: ShadowSettings,
+    ) -> ShadowMap {
+        let light_dir = light_dir.normalize();
+        // lookat can't build a basis when looking straight along `up`
+        let up = if Vertex3::cross(up, light_dir).norm() < 1e-4 {
+            Vertex3::init(0.0, 0.0, 1.0)
+        } else {
+            up
+        };
+        let size = (width.min(height) * 3 / 4).max(1);
+        let viewport = renderer::viewport((width - size) / 2, (height - size) / 2, size, size, DEPTH);
+        let light_view = renderer::lookat(light_dir, Vertex3::new(), up);
+        let transform = viewport * light_view * model.normalize_transform();
+
+        let mut framebuffer = Framebuffer::new(width, height);
+        let buffer = model.vertex_buffer();
+        let cache = TransformCache::new(buffer, &transform);
+        for (face, &triangle) in model.faces.iter().zip(buffer.triangles.iter()) {
+            let screen_coords = cache.triangle(triangle).map(|coord| coord.to_vector());
+            let shader = DepthShader {
+                model,
+                face: *face,
+                varying_uv: triangle.map(|index| buffer.vertices[index as usize].uv),
+                // opaque faces have nothing to cut out
+                alpha_cutoff: settings.alpha_cutoff.filter(|_| model.is_transparent(face)),
+            };
+            renderer::triangle(&screen_coords, shader, &mut framebuffer);
+        }
+
+        ShadowMap {
+            width,
+            height,
+            depth: framebuffer.zbuffer,
+            transform,
+            settings,
+        }
+    }
+
+    /// Fraction of the filter footprint around `world` that the light
+    /// reaches, from 0 (fully occluded) to 1 (fully lit).
+    pub fn visibility(&self, world: Vertex3<f32>) -> f32 {
+        let p = (self.transform.clone() * world.to_matrix()).to_vector();
+        let radius = self.settings.pcf_radius as i32;
+        let (cx, cy) = (p.x.round() as i32, p.y.round() as i32);
+        let mut lit = 0;
+        let mut samples = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                samples += 1;
+                let (x, y) = (cx + dx, cy + dy);
+                // anything outside of the map is treated as lit
+                if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+                    lit += 1;
+                    continue;
+                }
+                let occluder = self.depth[(x + y * self.width as i32) as usize];
+                if p.z + self.settings.bias >= occluder {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / samples as f32
+    }
+}
+
+/// Wraps another shader and darkens whatever it produces where the shadow
+/// map says the light is blocked.
+pub struct ShadowShader<'a, S: Shader> {
+    inner: S,
+    shadow_map: &'a ShadowMap,
+    varying_pos: [Vertex3<f32>; 3],
+}
+
+impl<'a, S: Shader> ShadowShader<'a, S> {
+    pub fn new(inner: S, shadow_map: &'a ShadowMap, positions: [Vertex3<f32>; 3]) -> ShadowShader<'a, S> {
+        ShadowShader {
+            inner,
+            shadow_map,
+            varying_pos: positions,
+        }
+    }
+}
+
+impl<'a, S: Shader> Shader for ShadowShader<'a, S> {
+    fn fragment(&self, bar: Vertex3<f32>, derivatives: Derivatives, pixel: &mut Color) -> bool {
+        if !self.inner.fragment(bar, derivatives, pixel) {
+            return false;
+        }
+        let world = (self.varying_pos[0] * bar.x)
+            + (self.varying_pos[1] * bar.y)
+            + (self.varying_pos[2] * bar.z);
+        let ambient = self.shadow_map.settings.ambient;
+        let shade = ambient + (1.0 - ambient) * self.shadow_map.visibility(world);
+        pixel.r = (pixel.r as f32 * shade) as u8;
+        pixel.g = (pixel.g as f32 * shade) as u8;
+        pixel.b = (pixel.b as f32 * shade) as u8;
+        true
+    }
+
+    fn blend(&self) -> Blend {
+        self.inner.blend()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use material::Material;
+    use texture::Texture;
+
+    // a small square floating above a large floor, both facing +z
+    fn occluder_model() -> Model {
+        let verts = vec![
+            Vertex3::init(-1.0, -1.0, 0.0),
+            Vertex3::init(1.0, -1.0, 0.0),
+            Vertex3::init(1.0, 1.0, 0.0),
+            Vertex3::init(-1.0, 1.0, 0.0),
+            Vertex3::init(-0.2, -0.2, 0.5),
+            Vertex3::init(0.2, -0.2, 0.5),
+            Vertex3::init(0.2, 0.2, 0.5),
+            Vertex3::init(-0.2, 0.2, 0.5),
+        ];
+        let face = |a, b, c| Face {
+            vertexes: [a, b, c],
+            textures: None,
+            normals: None,
+            tangents: None,
+            material: None,
+            smoothing_group: None,
+        };
+        let mut model = Model::empty();
+        model.verts = verts;
+        model.textures = vec![Vertex3::new()];
+        model.normals = vec![Vertex3::init(0.0, 0.0, 1.0)];
+        model.faces = vec![face(0, 1, 2), face(0, 2, 3), face(4, 5, 6), face(4, 6, 7)];
+        model.texture_image = Texture::from_color(Rgb([0, 0, 0]));
+        model
+    }
+
+    fn shadow_map(pcf_radius: u32) -> ShadowMap {
+        let settings = ShadowSettings {
+            pcf_radius,
+            ..ShadowSettings::new()
+        };
+        shadow_map_of(&occluder_model(), settings)
+    }
+
+    fn shadow_map_of(model: &Model, settings: ShadowSettings) -> ShadowMap {
+        ShadowMap::new(
+            model,
+            Vertex3::init(0.0, 0.0, 1.0),
+            Vertex3::init(0.0, 1.0, 0.0),
+            64,
+            64,
+            settings,
+        )
+    }
+
+    #[test]
+    fn floor_under_occluder_is_in_shadow() {
+        let map = shadow_map(0);
+        assert!(map.visibility(Vertex3::init(0.0, 0.0, 0.0)) == 0.0);
+        assert!(map.visibility(Vertex3::init(0.8, 0.8, 0.0)) == 1.0);
+    }
+
+    #[test]
+    fn occluder_does_not_shadow_itself() {
+        let map = shadow_map(0);
+        assert!(map.visibility(Vertex3::init(0.0, 0.0, 0.5)) == 1.0);
+    }
+
+    #[test]
+    fn pcf_softens_shadow_edges() {
+        let map = shadow_map(1);
+        // 0.2 in model space lands exactly on the occluder's edge in the map
+        let edge = map.visibility(Vertex3::init(0.2, 0.0, 0.0));
+        assert!(edge > 0.0 && edge < 1.0);
+    }
+
+    #[test]
+    fn cut_out_parts_of_occluders_let_light_through() {
+        // the occluder turns fully see-through
+        let mut model = occluder_model();
+        let mut material = Material::new("net");
+        material.dissolve = 0.0;
+        model.materials.push(material);
+        model.faces[2].material = Some(0);
+        model.faces[3].material = Some(0);
+
+        let floor = Vertex3::init(0.0, 0.0, 0.0);
+        assert!(shadow_map_of(&model, ShadowSettings::new()).visibility(floor) < 1.0);
+        let settings = ShadowSettings {
+            alpha_cutoff: Some(0.5),
+            ..ShadowSettings::new()
+        };
+        assert!(shadow_map_of(&model, settings).visibility(floor) == 1.0);
+    }
+}
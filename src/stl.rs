@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str;
+use std::vec::Vec;
+
+use geometry::Vertex3;
+use model::{Face, Mesh, Model, ModelError};
+use triangulate::triangulate;
+
+/// Loads an ASCII or binary STL file.
+pub fn load(path: &Path) -> Result<Model, ModelError> {
+    let data = fs::read(path).map_err(|error| ModelError::Open {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    from_slice(&data)
+}
+
+/// Builds a model from STL data. Binary files may start with `solid` as
+/// well, so ASCII is only assumed when the size doesn't match the triangle
+/// count of a binary file and there are no NUL bytes.
+///
+/// STL stores every triangle on its own, so corners at the same position
+/// are merged into one vertex. Facet normals are kept and the faces are
+/// drawn flat, which is what CAD exports expect.
+pub fn from_slice(data: &[u8]) -> Result<Model, ModelError> {
+    let mut builder = Builder::new();
+    let binary_len = if data.len() >= 84 {
+        Some(84 + 50 * u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize)
+    } else {
+        None
+    };
+    if binary_len != Some(data.len()) && data.starts_with(b"solid") && !data.contains(&0) {
+        parse_ascii(data, &mut builder)?;
+    } else {
+        parse_binary(data, &mut builder)?;
+    }
+    Ok(builder.finish())
+}
+
+fn parse_binary(data: &[u8], builder: &mut Builder) -> Result<(), ModelError> {
+    if data.len() < 84 {
+        return Err(malformed("binary file is shorter than its header"));
+    }
+    let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+    if data.len() < 84 + 50 * count {
+        return Err(malformed(format!("expected {} triangles but the file ends early", count)));
+    }
+    let float = |offset: usize| f32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+    let vertex = |offset: usize| Vertex3::init(float(offset), float(offset + 4), float(offset + 8));
+    for triangle in 0..count {
+        // a normal, three corners and two bytes nobody agrees on
+        let offset = 84 + 50 * triangle;
+        let corners = [vertex(offset + 12), vertex(offset + 24), vertex(offset + 36)];
+        builder.facet(vertex(offset), &corners);
+    }
+    Ok(())
+}
+
+fn parse_ascii(data: &[u8], builder: &mut Builder) -> Result<(), ModelError> {
+    let text = str::from_utf8(data).map_err(|_| malformed("ASCII file is not valid text"))?;
+    let mut normal = Vertex3::new();
+    let mut corners: Vec<Vertex3<f32>> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let mut values = line.split_whitespace();
+        let statement = match values.next() {
+            Some(statement) => statement,
+            None => continue,
+        };
+        let vector = |values: &mut str::SplitWhitespace| -> Result<Vertex3<f32>, ModelError> {
+            let mut coords = [0.0; 3];
+            for coord in coords.iter_mut() {
+                let value = values.next().ok_or_else(|| ModelError::MissingValues {
+                    line: line_number,
+                    statement: statement.to_string(),
+                })?;
+                *coord = value.parse().map_err(|_| ModelError::InvalidNumber {
+                    line: line_number,
+                    value: value.to_string(),
+                })?;
+            }
+            Ok(Vertex3::init(coords[0], coords[1], coords[2]))
+        };
+        match statement {
+            "facet" => {
+                // `facet normal nx ny nz`
+                values.next();
+                normal = vector(&mut values)?;
+                corners.clear();
+            }
+            "vertex" => corners.push(vector(&mut values)?),
+            "endfacet" => {
+                if corners.len() < 3 {
+                    return Err(ModelError::MissingValues {
+                        line: line_number,
+                        statement: "facet".to_string(),
+                    });
+                }
+                builder.facet(normal, &corners);
+            }
+            // solid, outer loop, endloop and endsolid carry nothing
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Collects facets into a model, merging corners at the same position.
+struct Builder {
+    model: Model,
+    vertices: HashMap<[u32; 3], u32>,
+    normals: HashMap<[u32; 3], u32>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            model: Model::empty(),
+            vertices: HashMap::new(),
+            normals: HashMap::new(),
+        }
+    }
+
+    fn facet(&mut self, normal: Vertex3<f32>, corners: &[Vertex3<f32>]) {
+        let indices: Vec<u32> = corners.iter().map(|&corner| self.vertex(corner)).collect();
+        // exporters may leave the normal zeroed for the reader to work out
+        let normal = if normal.norm() > 0.0 {
+            let normal = normal.normalize();
+            let bits = [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()];
+            let normals = &mut self.model.normals;
+            Some(*self.normals.entry(bits).or_insert_with(|| {
+                normals.push(normal);
+                normals.len() as u32 - 1
+            }))
+        } else {
+            None
+        };
+        for triangle in triangulate(corners) {
+            self.model.faces.push(Face {
+                vertexes: triangle.map(|i| indices[i]),
+                textures: None,
+                normals: normal.map(|n| [n; 3]),
+                tangents: None,
+                material: None,
+                smoothing_group: None,
+            });
+        }
+    }
+
+    fn vertex(&mut self, position: Vertex3<f32>) -> u32 {
+        let bits = [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()];
+        let verts = &mut self.model.verts;
+        *self.vertices.entry(bits).or_insert_with(|| {
+            verts.push(position);
+            verts.len() as u32 - 1
+        })
+    }
+
+    fn finish(self) -> Model {
+        let mut model = self.model;
+        model.meshes.push(Mesh {
+            name: "default".to_string(),
+            faces: 0..model.faces.len(),
+            material: None,
+        });
+        model
+    }
+}
+
+fn malformed<S: Into<String>>(message: S) -> ModelError {
+    ModelError::Malformed {
+        format: "STL",
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a tetrahedron corner: three faces around the origin
+    const ASCII: &str = "solid corner\n\
+        facet normal 0 0 -1\n outer loop\n  vertex 0 0 0\n  vertex 0 1 0\n  vertex 1 0 0\n endloop\nendfacet\n\
+        facet normal 0 -1 0\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 0 0 1\n endloop\nendfacet\n\
+        facet normal 0 0 0\n outer loop\n  vertex 0 0 0\n  vertex 0 0 1\n  vertex 0 1 0\n endloop\nendfacet\n\
+        endsolid corner\n";
+
+    #[test]
+    fn loads_ascii_stl() {
+        let model = from_slice(ASCII.as_bytes()).unwrap();
+        assert!(model.faces.len() == 3);
+        // the corners are shared between facets
+        assert!(model.verts.len() == 4);
+        assert!(model.faces[1].vertexes == [0, 2, 3]);
+        assert!(model.vertex_normal(&model.faces[1], 0) == Vertex3::init(0.0, -1.0, 0.0));
+        // a zeroed normal falls back to the flat normal of the face
+        assert!(model.faces[2].normals.is_none());
+        assert!(model.vertex_normal(&model.faces[2], 0) == Vertex3::init(-1.0, 0.0, 0.0));
+        assert!(model.meshes[0].faces == (0..3));
+    }
+
+    #[test]
+    fn loads_binary_stl() {
+        // the header starts with `solid`, which binary files are allowed to do
+        let mut data = b"solid but actually binary".to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        for value in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 0]);
+        let model = from_slice(&data).unwrap();
+        assert!(model.faces.len() == 1);
+        assert!(model.vertex(&model.faces[0], 2) == Vertex3::init(0.0, 1.0, 0.0));
+        assert!(model.vertex_normal(&model.faces[0], 0) == Vertex3::init(0.0, 0.0, 1.0));
+
+        data.truncate(100);
+        match from_slice(&data) {
+            Err(ModelError::Malformed { format: "STL", .. }) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn reports_bad_ascii_numbers() {
+        match from_slice(b"solid x\nfacet normal 0 0 1\nouter loop\nvertex 0 zero 0\n") {
+            Err(ModelError::InvalidNumber { line: 4, ref value }) if value == "zero" => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use geometry::{Vertex3, Vertex4};
+use model::Model;
+use std::collections::HashMap;
+use std::f32;
+use std::vec::Vec;
+
+// Position, texture and normal index of a corner, and whether its face
+// keeps the orientation of the texture.
+type CornerKey = (u32, u32, Option<u32>, bool);
+
+/// Gives every textured face a tangent per corner, following the same rules
+/// as MikkTSpace so normal maps baked by other tools line up:
+///
+/// - the tangent points along increasing u, the bitangent along increasing v,
+/// - both are made perpendicular to the vertex normal and averaged over the
+///   faces sharing a vertex, weighted by the angle of their corners,
+/// - faces whose UVs are mirrored don't share tangents with the others and
+///   get a handedness of -1 in `w`, so the bitangent is
+///   `w * cross(normal, tangent)` everywhere.
+///
+/// Vertices are shared when they have the same position, texture and normal
+/// indices. Faces without texture coordinates get no tangents, and faces
+/// that already have tangents keep them.
+pub fn generate(model: &mut Model) {
+    let mut sums: HashMap<CornerKey, Vertex3<f32>> = HashMap::new();
+    let mut corners: Vec<(usize, [CornerKey; 3])> = Vec::new();
+
+    for (f, face) in model.faces.iter().enumerate() {
+        let textures = match face.textures {
+            Some(textures) if face.tangents.is_none() => textures,
+            _ => continue,
+        };
+        let p = [model.vertex(face, 0), model.vertex(face, 1), model.vertex(face, 2)];
+        let uv = [model.vertex_uv(face, 0), model.vertex_uv(face, 1), model.vertex_uv(face, 2)];
+        let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+        let (s1, t1) = (uv[1].x - uv[0].x, uv[1].y - uv[0].y);
+        let (s2, t2) = (uv[2].x - uv[0].x, uv[2].y - uv[0].y);
+        // twice the signed area of the triangle in texture space
+        let area = s1 * t2 - s2 * t1;
+        let preserves_orientation = area > 0.0;
+        let direction = (e1 * t2 - e2 * t1) * if preserves_orientation { 1.0 } else { -1.0 };
+
+        let mut keys = [(0, 0, None, true); 3];
+        for i in 0..3 {
+            let key = (face.vertexes[i], textures[i], face.get_normal(i), preserves_orientation);
+            keys[i] = key;
+            let sum = sums.entry(key).or_insert_with(Vertex3::new);
+            // a triangle without any extent in texture space doesn't know
+            // where u points and is left out of the average
+            if area == 0.0 || direction.norm() == 0.0 {
+                continue;
+            }
+            let normal = model.vertex_normal(face, i);
+            let tangent = direction - normal * (normal * direction);
+            if tangent.norm() > 0.0 {
+                *sum = *sum + tangent.normalize() * corner_angle(&p, i);
+            }
+        }
+        corners.push((f, keys));
+    }
+
+    let mut indices: HashMap<CornerKey, u32> = HashMap::new();
+    for (f, keys) in corners {
+        let mut face_tangents = [0; 3];
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(&index) = indices.get(key) {
+                face_tangents[i] = index;
+                continue;
+            }
+            let normal = model.vertex_normal(&model.faces[f], i);
+            let sum = sums[key];
+            let tangent = if sum.norm() > 0.0 { sum.normalize() } else { perpendicular(normal) };
+            let handedness = if key.3 { 1.0 } else { -1.0 };
+            let index = model.tangents.len() as u32;
+            model.tangents.push(Vertex4::init(tangent.x, tangent.y, tangent.z, handedness));
+            indices.insert(*key, index);
+            face_tangents[i] = index;
+        }
+        model.faces[f].tangents = Some(face_tangents);
+    }
+}
+
+// The interior angle of the triangle at corner `i`.
+fn corner_angle(p: &[Vertex3<f32>; 3], i: usize) -> f32 {
+    let a = p[(i + 1) % 3] - p[i];
+    let b = p[(i + 2) % 3] - p[i];
+    let lengths = a.norm() * b.norm();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+    (a * b / lengths).clamp(-1.0, 1.0).acos()
+}
+
+// Any unit vector at right angles to `normal`, for vertices whose faces
+// don't tell which way u goes.
+fn perpendicular(normal: Vertex3<f32>) -> Vertex3<f32> {
+    let axis = if normal.x.abs() < 0.9 {
+        Vertex3::init(1.0, 0.0, 0.0)
+    } else {
+        Vertex3::init(0.0, 1.0, 0.0)
+    };
+    let tangent = Vertex3::cross(normal, axis);
+    if tangent.norm() > 0.0 {
+        tangent.normalize()
+    } else {
+        axis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(obj: &str) -> Model {
+        Model::from_reader(obj.as_bytes()).unwrap()
+    }
+
+    fn close(a: Vertex3<f32>, b: Vertex3<f32>) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    fn xyz(v: Vertex4<f32>) -> Vertex3<f32> {
+        Vertex3::init(v.x, v.y, v.z)
+    }
+
+    #[test]
+    fn tangents_follow_the_texture_axes() {
+        // u runs against x and v along y, which mirrors the texture
+        let model = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 0 1\nvt 1 1\nvt 1 0\nf 1/4 2/1 3/2 4/3\n");
+        for face in model.faces.iter() {
+            for i in 0..3 {
+                let tangent = model.vertex_tangent(face, i).unwrap();
+                assert!(close(xyz(tangent), Vertex3::init(-1.0, 0.0, 0.0)));
+                assert!(tangent.w == -1.0);
+                let bitangent = model.vertex_bitangent(face, i).unwrap();
+                assert!(close(bitangent, Vertex3::init(0.0, 1.0, 0.0)));
+            }
+        }
+        assert!(model.tangents.len() == 4);
+    }
+
+    #[test]
+    fn tangents_are_perpendicular_to_smooth_normals() {
+        // two faces folded along the y axis, with u running across the fold
+        let model = parse(
+            "v -1 0 1\nv 0 0 0\nv 0 1 0\nv -1 1 1\nv 1 0 1\nv 1 1 1\n\
+             vt 0 0\nvt 0.5 0\nvt 0.5 1\nvt 0 1\nvt 1 0\nvt 1 1\n\
+             f 1/1 2/2 3/3 4/4\nf 2/2 5/5 6/6 3/3\n",
+        );
+        let face = &model.faces[0];
+        let tangent = model.vertex_tangent(face, 1).unwrap();
+        assert!((xyz(tangent) * model.vertex_normal(face, 1)).abs() < 1e-5);
+        // on the fold the averaged tangent runs straight across it
+        assert!(close(xyz(tangent), Vertex3::init(1.0, 0.0, 0.0)));
+        assert!(tangent.w == 1.0);
+    }
+
+    #[test]
+    fn mirrored_uvs_do_not_share_tangents() {
+        // the second face reuses the first face's UVs mirrored across the
+        // shared edge, like a symmetric character unwrap
+        let model = parse(
+            "v -1 0 0\nv 0 0 0\nv 0 1 0\nv 1 0 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\n\
+             f 1/1 2/2 3/3\nf 2/2 4/1 3/3\n",
+        );
+        let first = model.vertex_tangent(&model.faces[0], 1).unwrap();
+        let second = model.vertex_tangent(&model.faces[1], 0).unwrap();
+        assert!(model.faces[0].tangents.unwrap()[1] != model.faces[1].tangents.unwrap()[0]);
+        assert!(first.w == 1.0 && close(xyz(first), Vertex3::init(1.0, 0.0, 0.0)));
+        assert!(second.w == -1.0 && close(xyz(second), Vertex3::init(-1.0, 0.0, 0.0)));
+        // v runs up on both sides
+        assert!(close(model.vertex_bitangent(&model.faces[0], 1).unwrap(), Vertex3::init(0.0, 1.0, 0.0)));
+        assert!(close(model.vertex_bitangent(&model.faces[1], 0).unwrap(), Vertex3::init(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn faces_without_texture_coordinates_get_no_tangents() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n");
+        assert!(model.faces[0].tangents.is_none());
+        assert!(model.vertex_tangent(&model.faces[0], 0).is_none());
+    }
+}
@@ -0,0 +1,418 @@
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive, Rgb, RgbImage, Rgba};
+
+use geometry::{Derivatives, Vertex3};
+
+/// What happens to texture coordinates outside of [0, 1].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wrap {
+    /// The texture tiles.
+    Repeat,
+    /// The texture tiles, flipping every other copy.
+    MirroredRepeat,
+    /// The edge texels stretch out forever.
+    ClampToEdge,
+}
+
+/// How texels are combined for coordinates between their centers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    /// The closest texel of the full size image.
+    Nearest,
+    /// A weighted average of the four closest texels of the full size image.
+    Bilinear,
+    /// Bilinear samples from the two mip levels closest to the size of the
+    /// pixel's footprint on the texture, blended together.
+    Trilinear,
+}
+
+/// How a texture is looked up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sampler {
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    pub filter: Filter,
+    /// Most trilinear samples taken along a footprint stretched in one
+    /// direction, as happens on surfaces seen at a grazing angle. 1 turns
+    /// anisotropic filtering off.
+    pub anisotropy: u32,
+}
+
+impl Sampler {
+    /// Repeating and trilinear, what OBJ textures are usually made for.
+    pub fn new() -> Sampler {
+        Sampler {
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+            filter: Filter::Trilinear,
+            anisotropy: 1,
+        }
+    }
+
+    /// Color of `texture` at `uv`, with (0, 0) at the bottom left corner of
+    /// the first texel and (1, 1) at the top right of the last. `duv` is how
+    /// `uv` changes between neighbouring pixels, which picks the mip level.
+    /// Channels are in [0, 1].
+    pub fn sample(&self, texture: &Texture, uv: Vertex3<f32>, duv: Derivatives) -> Rgba<f32> {
+        let color = match self.filter {
+            Filter::Nearest => {
+                let image = &texture.levels[0];
+                let x = uv.x * image.width() as f32;
+                let y = uv.y * image.height() as f32;
+                self.texel(image, x.floor() as i64, y.floor() as i64)
+            }
+            Filter::Bilinear => self.bilinear(&texture.levels[0], uv),
+            Filter::Trilinear => self.anisotropic(texture, uv, duv),
+        };
+        Rgba(color)
+    }
+
+    // Averages trilinear samples spread along the longer axis of the pixel's
+    // footprint, each picking its level from the footprint's width divided
+    // among the samples.
+    fn anisotropic(&self, texture: &Texture, uv: Vertex3<f32>, duv: Derivatives) -> [f32; 4] {
+        let (width, height) = (texture.width() as f32, texture.height() as f32);
+        let texels = |d: Vertex3<f32>| ((d.x * width).powi(2) + (d.y * height).powi(2)).sqrt();
+        let (x, y) = (texels(duv.dx), texels(duv.dy));
+        let (major, longer, shorter) = if x >= y { (duv.dx, x, y) } else { (duv.dy, y, x) };
+
+        let most = self.anisotropy.max(1);
+        let samples = if shorter > 0.0 {
+            ((longer / shorter).ceil() as u32).clamp(1, most)
+        } else {
+            most
+        };
+        let lod = (longer / samples as f32).log2();
+        let mut color = [0.0; 4];
+        for i in 0..samples {
+            let offset = (i as f32 + 0.5) / samples as f32 - 0.5;
+            let sample = self.trilinear(texture, uv + major * offset, lod);
+            for c in 0..4 {
+                color[c] += sample[c] / samples as f32;
+            }
+        }
+        color
+    }
+
+    // Blends bilinear samples from the levels either side of `lod`.
+    fn trilinear(&self, texture: &Texture, uv: Vertex3<f32>, lod: f32) -> [f32; 4] {
+        // also sends NaN to the full size image
+        let lod = lod.max(0.0).min((texture.levels.len() - 1) as f32);
+        let level = lod.floor() as usize;
+        let fine = self.bilinear(&texture.levels[level], uv);
+        let t = lod - level as f32;
+        if t == 0.0 {
+            return fine;
+        }
+        let coarse = self.bilinear(&texture.levels[level + 1], uv);
+        [0, 1, 2, 3].map(|c| fine[c] + (coarse[c] - fine[c]) * t)
+    }
+
+    fn bilinear(&self, image: &Level, uv: Vertex3<f32>) -> [f32; 4] {
+        // texel centers sit half way between their edges
+        let x = uv.x * image.width() as f32 - 0.5;
+        let y = uv.y * image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let corners = [
+            (self.texel(image, x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (self.texel(image, x0 + 1, y0), fx * (1.0 - fy)),
+            (self.texel(image, x0, y0 + 1), (1.0 - fx) * fy),
+            (self.texel(image, x0 + 1, y0 + 1), fx * fy),
+        ];
+        [0, 1, 2, 3].map(|c| corners.iter().map(|&(texel, weight)| texel[c] * weight).sum())
+    }
+
+    // The texel at a position that may lie outside of the image.
+    fn texel(&self, image: &Level, x: i64, y: i64) -> [f32; 4] {
+        let x = wrap(self.wrap_u, x, image.width());
+        let y = wrap(self.wrap_v, y, image.height());
+        image.get_pixel(x, y).0
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Sampler {
+        Sampler::new()
+    }
+}
+
+fn wrap(mode: Wrap, i: i64, size: u32) -> u32 {
+    let size = size as i64;
+    let wrapped = match mode {
+        Wrap::Repeat => i.rem_euclid(size),
+        Wrap::MirroredRepeat => {
+            let i = i.rem_euclid(2 * size);
+            if i < size {
+                i
+            } else {
+                2 * size - 1 - i
+            }
+        }
+        Wrap::ClampToEdge => i.clamp(0, size - 1),
+    };
+    wrapped as u32
+}
+
+// Halves the image along each axis that is longer than one texel. Each new
+// texel averages the area it covers, so on odd sizes it takes part of a
+// third texel instead of leaving the last row or column out.
+fn downsample(image: &Level) -> Level {
+    let (width, height) = (image.width(), image.height());
+    let (columns, rows) = (taps(width, (width / 2).max(1)), taps(height, (height / 2).max(1)));
+    Level::from_fn(columns.len() as u32, rows.len() as u32, |x, y| {
+        let mut sum = [0.0; 4];
+        for &(sx, wx) in columns[x as usize].iter() {
+            for &(sy, wy) in rows[y as usize].iter() {
+                let texel = image.get_pixel(sx, sy);
+                for c in 0..4 {
+                    sum[c] += texel[c] * wx * wy;
+                }
+            }
+        }
+        Rgba(sum)
+    })
+}
+
+// For each texel of an axis shrunk from `size` to `scaled` texels, the
+// texels it covers before shrinking and the share of each in its average.
+fn taps(size: u32, scaled: u32) -> Vec<Vec<(u32, f32)>> {
+    let scale = size as f32 / scaled as f32;
+    (0..scaled)
+        .map(|i| {
+            let (start, end) = (i as f32 * scale, (i + 1) as f32 * scale);
+            (start.floor() as u32..(end.ceil() as u32).min(size))
+                .map(|s| (s, (end.min(s as f32 + 1.0) - start.max(s as f32)) / scale))
+                .collect()
+        })
+        .collect()
+}
+
+// One mip level, as RGBA in [0, 1] whatever the format of the image it came
+// from.
+type Level = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+// Converts an image to RGBA floats, keeping the precision of 16 bit images.
+fn to_level(image: &DynamicImage) -> Level {
+    match *image {
+        DynamicImage::ImageLuma16(ref image) => convert(image, 65535.0),
+        DynamicImage::ImageLumaA16(ref image) => convert(image, 65535.0),
+        DynamicImage::ImageRgb16(ref image) => convert(image, 65535.0),
+        DynamicImage::ImageRgba16(ref image) => convert(image, 65535.0),
+        ref image => convert(&image.to_rgba8(), 255.0),
+    }
+}
+
+fn convert<P>(image: &ImageBuffer<P, Vec<P::Subpixel>>, max: f32) -> Level
+where
+    P: Pixel + 'static,
+    P::Subpixel: Primitive + Into<f32> + 'static,
+{
+    Level::from_fn(image.width(), image.height(), |x, y| {
+        let texel = image.get_pixel(x, y).to_rgba();
+        Rgba(texel.0.map(|c| c.into() / max))
+    })
+}
+
+/// An image ready to be sampled, along with the sampler to use for it.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    // the full size image followed by ever smaller halvings of it, down to a
+    // single texel
+    levels: Vec<Level>,
+    opaque: bool,
+    pub sampler: Sampler,
+}
+
+impl Texture {
+    /// Converts an image of any format and builds its mip chain. Images
+    /// without alpha are opaque. Sampled with `Sampler::new`.
+    pub fn new(image: DynamicImage) -> Texture {
+        let mut levels = vec![to_level(&image)];
+        while let Some(last) = levels.last().filter(|level| level.width() > 1 || level.height() > 1) {
+            let next = downsample(last);
+            levels.push(next);
+        }
+        let opaque = levels[0].pixels().all(|texel| texel[3] >= 1.0);
+        Texture {
+            levels,
+            opaque,
+            sampler: Sampler::new(),
+        }
+    }
+
+    /// A texture of a single texel.
+    pub fn from_color(color: Rgb<u8>) -> Texture {
+        Texture::new(DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, color)))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height()
+    }
+
+    /// Whether every texel has an alpha of 1.
+    pub fn is_opaque(&self) -> bool {
+        self.opaque
+    }
+
+    /// Number of mip levels, including the full size image.
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Color at `uv` using the texture's own sampler.
+    pub fn sample(&self, uv: Vertex3<f32>, duv: Derivatives) -> Rgba<f32> {
+        self.sampler.sample(self, uv, duv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 2x2 texture: black and red on the bottom row, green and white above
+    fn checker(filter: Filter, wrap: Wrap) -> Texture {
+        let mut image = RgbImage::new(2, 2);
+        image.put_pixel(0, 0, Rgb([0, 0, 0]));
+        image.put_pixel(1, 0, Rgb([255, 0, 0]));
+        image.put_pixel(0, 1, Rgb([0, 255, 0]));
+        image.put_pixel(1, 1, Rgb([255, 255, 255]));
+        let mut texture = Texture::new(DynamicImage::ImageRgb8(image));
+        texture.sampler = Sampler {
+            wrap_u: wrap,
+            wrap_v: wrap,
+            filter,
+            anisotropy: 1,
+        };
+        texture
+    }
+
+    fn uv(u: f32, v: f32) -> Vertex3<f32> {
+        Vertex3::init(u, v, 0.0)
+    }
+
+    // the color channels of a sample in 8 bits
+    fn bytes(color: Rgba<f32>) -> Rgb<u8> {
+        Rgb([color[0], color[1], color[2]].map(|c| (c * 255.0).round() as u8))
+    }
+
+    #[test]
+    fn nearest_uses_u_for_columns_and_v_for_rows() {
+        // a texture wider than it is tall, which used to be read transposed
+        let mut image = RgbImage::new(4, 1);
+        image.put_pixel(3, 0, Rgb([9, 9, 9]));
+        let mut texture = Texture::new(DynamicImage::ImageRgb8(image));
+        texture.sampler.filter = Filter::Nearest;
+        assert!(bytes(texture.sample(uv(0.9, 0.5), Derivatives::zero())) == Rgb([9, 9, 9]));
+        assert!(bytes(texture.sample(uv(0.1, 0.9), Derivatives::zero())) == Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let sample = |wrap, u| bytes(checker(Filter::Nearest, wrap).sample(uv(u, 0.25), Derivatives::zero()))[0];
+        // a quarter past the right edge
+        assert!(sample(Wrap::Repeat, 1.25) == 0);
+        assert!(sample(Wrap::MirroredRepeat, 1.25) == 255);
+        assert!(sample(Wrap::ClampToEdge, 1.25) == 255);
+        // a quarter before the left edge
+        assert!(sample(Wrap::Repeat, -0.25) == 255);
+        assert!(sample(Wrap::MirroredRepeat, -0.25) == 0);
+        assert!(sample(Wrap::ClampToEdge, -0.25) == 0);
+        // far away coordinates never panic
+        assert!(sample(Wrap::ClampToEdge, 1e9) == 255);
+    }
+
+    #[test]
+    fn bilinear_blends_neighbouring_texels() {
+        let texture = checker(Filter::Bilinear, Wrap::ClampToEdge);
+        // texel centers come out exactly
+        assert!(bytes(texture.sample(uv(0.25, 0.25), Derivatives::zero())) == Rgb([0, 0, 0]));
+        assert!(bytes(texture.sample(uv(0.75, 0.75), Derivatives::zero())) == Rgb([255, 255, 255]));
+        // half way between all four texels
+        assert!(bytes(texture.sample(uv(0.5, 0.5), Derivatives::zero())) == Rgb([128, 128, 64]));
+        // half way along the bottom row
+        assert!(bytes(texture.sample(uv(0.5, 0.25), Derivatives::zero())) == Rgb([128, 0, 0]));
+    }
+
+    // derivatives of a footprint `x` texels wide and `y` texels tall on a
+    // texture of `size` texels
+    fn footprint(size: u32, x: f32, y: f32) -> Derivatives {
+        let size = size as f32;
+        Derivatives {
+            dx: uv(x / size, 0.0),
+            dy: uv(0.0, y / size),
+        }
+    }
+
+    // a texture of black and white stripes one texel wide
+    fn stripes(size: u32) -> Texture {
+        let image = RgbImage::from_fn(size, size, |x, _| if x % 2 == 0 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) });
+        Texture::new(DynamicImage::ImageRgb8(image))
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_texel() {
+        let texture = checker(Filter::Trilinear, Wrap::Repeat);
+        assert!(texture.levels() == 2);
+        assert!(texture.levels[1].get_pixel(0, 0) == &Rgba([0.5, 0.5, 0.25, 1.0]));
+
+        let wide = Texture::new(DynamicImage::ImageRgb8(RgbImage::new(8, 3)));
+        let sizes: Vec<(u32, u32)> = wide.levels.iter().map(|level| level.dimensions()).collect();
+        assert!(sizes == vec![(8, 3), (4, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn downsampling_odd_sizes_keeps_every_texel() {
+        // only the last column is white, the one halving 5 texels to 2
+        // would leave out
+        let image = RgbImage::from_fn(5, 3, |x, _| if x == 4 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) });
+        let texture = Texture::new(DynamicImage::ImageRgb8(image));
+        let red = |level: usize, x: u32| texture.levels[level].get_pixel(x, 0)[0];
+        assert!(texture.levels[1].dimensions() == (2, 1));
+        assert!(red(1, 0) == 0.0 && (red(1, 1) - 0.4).abs() < 1e-6);
+        // the average of the whole image stays the same down to one texel
+        assert!((red(2, 0) - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn trilinear_picks_the_level_matching_the_footprint() {
+        let texture = stripes(8);
+        let at = |x: f32, y: f32| bytes(texture.sample(uv(0.6875, 0.5), footprint(8, x, y)))[0];
+        // one texel per pixel shows the stripes as they are
+        assert!(at(1.0, 1.0) == 255);
+        // two or more texels per pixel average them out
+        assert!(at(2.0, 2.0) == 128);
+        assert!(at(8.0, 8.0) == 128);
+        // half way between the two
+        assert!(at(1.5, 1.5) > 128 && at(1.5, 1.5) < 255);
+    }
+
+    #[test]
+    fn anisotropy_keeps_detail_across_a_stretched_footprint() {
+        let mut texture = stripes(64);
+        // four texels across the stripes but one along them
+        let duv = footprint(64, 1.0, 4.0);
+        let isotropic = bytes(texture.sample(uv(0.5234375, 0.5), duv));
+        texture.sampler.anisotropy = 4;
+        let anisotropic = bytes(texture.sample(uv(0.5234375, 0.5), duv));
+        assert!(isotropic == Rgb([128, 128, 128]));
+        assert!(anisotropic == Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn every_format_becomes_rgba_floats() {
+        let sample = |image: DynamicImage| Texture::new(image).sample(uv(0.5, 0.5), Derivatives::zero());
+        let gray = sample(DynamicImage::ImageLuma8(image::GrayImage::from_pixel(1, 1, image::Luma([51]))));
+        assert!(gray == Rgba([0.2, 0.2, 0.2, 1.0]));
+        let translucent = sample(DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 51]))));
+        assert!(translucent == Rgba([1.0, 0.0, 0.0, 0.2]));
+        // 16 bit images keep detail that 8 bits can't hold
+        let deep = sample(DynamicImage::ImageRgba16(ImageBuffer::from_pixel(1, 1, Rgba([32768u16, 0, 65535, 65535]))));
+        assert!(deep[0] == 32768.0 / 65535.0);
+        assert!(deep[2] == 1.0 && deep[3] == 1.0);
+    }
+}
@@ -0,0 +1,341 @@
+use clipping::Frustum;
+use framebuffer::RenderTarget;
+use geometry::{Vertex3, Vertex4};
+use renderer::{self, ClippedShader, Interpolation, Shader};
+use sdl2::pixels::Color;
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::vec::Vec;
+
+pub const TILE_SIZE: u32 = 64;
+
+// What the target held before the tiles were drawn, row by row.
+struct Screen {
+    width: u32,
+    colors: Vec<Color>,
+    depth: Vec<f32>,
+}
+
+// A triangle that survived clipping, referring back to the shader of the
+// face it came from. `corners` is set for pieces of clipped triangles.
+struct Primitive {
+    clip_coords: [Vertex4<f32>; 3],
+    corners: Option<[Vertex3<f32>; 3]>,
+    shader: usize,
+}
+
+/// One screen tile with its own color and depth buffers. Pixels are only
+/// copied back to the real target when they were written; until then they
+/// read as the target's colors, for blending.
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<Option<Color>>,
+    background: Vec<Color>,
+    zbuffer: Vec<f32>,
+}
+
+impl Tile {
+    fn new(x: u32, y: u32, width: u32, height: u32, screen: &Screen) -> Tile {
+        let mut background = Vec::with_capacity((width * height) as usize);
+        let mut zbuffer = Vec::with_capacity((width * height) as usize);
+        for ty in 0..height {
+            let row = ((y + ty) * screen.width + x) as usize;
+            background.extend_from_slice(&screen.colors[row..row + width as usize]);
+            zbuffer.extend_from_slice(&screen.depth[row..row + width as usize]);
+        }
+        Tile {
+            x,
+            y,
+            width,
+            height,
+            pixels: vec![None; (width * height) as usize],
+            background,
+            zbuffer,
+        }
+    }
+
+    fn copy_to<T: RenderTarget>(&self, target: &mut T) {
+        for ty in 0..self.height {
+            for tx in 0..self.width {
+                let idx = (tx + ty * self.width) as usize;
+                if let Some(pixel) = self.pixels[idx] {
+                    target.set_depth(self.x + tx, self.y + ty, self.zbuffer[idx]);
+                    target.set_pixel(self.x + tx, self.y + ty, pixel);
+                }
+            }
+        }
+    }
+}
+
+impl RenderTarget for Tile {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Color {
+        let idx = (x + y * self.width) as usize;
+        self.pixels[idx].unwrap_or(self.background[idx])
+    }
+
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.zbuffer[(x + y * self.width) as usize]
+    }
+
+    fn set_depth(&mut self, x: u32, y: u32, z: f32) {
+        self.zbuffer[(x + y * self.width) as usize] = z;
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(x + y * self.width) as usize] = Some(color);
+    }
+
+    fn clear(&mut self, _color: Color) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = None;
+        }
+    }
+}
+
+/// Clips the triangles, bins them into `TILE_SIZE` screen tiles and shades
+/// the tiles on `threads` threads. Within a tile triangles are drawn in the
+/// order given, so the result matches drawing them one after another with
+/// `renderer::clipped_triangle`.
+pub fn render<S: Shader + Sync, T: RenderTarget>(
+    frustum: &Frustum,
+    triangles: &[([Vertex4<f32>; 3], S)],
+    interpolation: Interpolation,
+    threads: usize,
+    target: &mut T,
+) -> i32 {
+    let (width, height) = (target.width(), target.height());
+    let columns = width.div_ceil(TILE_SIZE);
+    let rows = height.div_ceil(TILE_SIZE);
+
+    let primitives = clip(frustum, triangles);
+    let mut bins: Vec<Vec<usize>> = vec![Vec::new(); (columns * rows) as usize];
+    for (index, primitive) in primitives.iter().enumerate() {
+        if let Some((x0, y0, x1, y1)) = tile_range(&primitive.clip_coords, width, height) {
+            for row in y0..=y1 {
+                for column in x0..=x1 {
+                    bins[(column + row * columns) as usize].push(index);
+                }
+            }
+        }
+    }
+
+    let mut screen = Screen {
+        width,
+        colors: Vec::with_capacity((width * height) as usize),
+        depth: Vec::with_capacity((width * height) as usize),
+    };
+    for y in 0..height {
+        for x in 0..width {
+            screen.colors.push(target.pixel(x, y));
+            screen.depth.push(target.depth(x, y));
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let finished: Vec<(Tile, i32)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= bins.len() {
+                            break;
+                        }
+                        let (column, row) = (index as u32 % columns, index as u32 / columns);
+                        let (x, y) = (column * TILE_SIZE, row * TILE_SIZE);
+                        let mut tile = Tile::new(
+                            x,
+                            y,
+                            cmp::min(TILE_SIZE, width - x),
+                            cmp::min(TILE_SIZE, height - y),
+                            &screen,
+                        );
+                        let mut count = 0;
+                        for &p in bins[index].iter() {
+                            let primitive = &primitives[p];
+                            let coords = &primitive.clip_coords;
+                            let shader = &triangles[primitive.shader].1;
+                            count += match primitive.corners {
+                                Some(corners) => {
+                                    let piece = ClippedShader { inner: shader, corners };
+                                    renderer::rasterize_at(coords, interpolation, piece, &mut tile, (x, y))
+                                }
+                                None => renderer::rasterize_at(coords, interpolation, shader, &mut tile, (x, y)),
+                            };
+                        }
+                        done.push((tile, count));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    });
+
+    let mut render_count = 0;
+    for (tile, count) in finished.iter() {
+        tile.copy_to(target);
+        render_count += count;
+    }
+    render_count
+}
+
+// Same clipping as `renderer::clipped_triangle`, keeping the pieces around
+// so they can be binned.
+fn clip<S>(frustum: &Frustum, triangles: &[([Vertex4<f32>; 3], S)]) -> Vec<Primitive> {
+    let mut primitives = Vec::with_capacity(triangles.len());
+    for (shader, &(clip_coords, _)) in triangles.iter().enumerate() {
+        if clip_coords.iter().all(|&v| frustum.contains(v)) {
+            primitives.push(Primitive {
+                clip_coords,
+                corners: None,
+                shader,
+            });
+            continue;
+        }
+        let polygon = frustum.clip_triangle(&clip_coords);
+        for i in 1..polygon.len().saturating_sub(1) {
+            let corners = [polygon[0], polygon[i], polygon[i + 1]];
+            primitives.push(Primitive {
+                clip_coords: [corners[0].position, corners[1].position, corners[2].position],
+                corners: Some([corners[0].bar, corners[1].bar, corners[2].bar]),
+                shader,
+            });
+        }
+    }
+    primitives
+}
+
+// The inclusive range of tile columns and rows covered by the triangle's
+// screen bounding box, or None if it is off screen or the screen is empty.
+fn tile_range(
+    clip_coords: &[Vertex4<f32>; 3],
+    width: u32,
+    height: u32,
+) -> Option<(u32, u32, u32, u32)> {
+    let mut min = Vertex3::init(f32::INFINITY, f32::INFINITY, 0.0);
+    let mut max = Vertex3::init(f32::NEG_INFINITY, f32::NEG_INFINITY, 0.0);
+    for v in clip_coords.iter() {
+        let screen = v.to_vector();
+        min.x = min.x.min(screen.x);
+        min.y = min.y.min(screen.y);
+        max.x = max.x.max(screen.x);
+        max.y = max.y.max(screen.y);
+    }
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let max_x = (width - 1) as f32;
+    let max_y = (height - 1) as f32;
+    if !(max.x >= 0.0 && max.y >= 0.0 && min.x <= max_x && min.y <= max_y) {
+        return None;
+    }
+    let to_tile = |v: f32, limit: f32| (v.max(0.0).min(limit) as u32) / TILE_SIZE;
+    Some((
+        to_tile(min.x, max_x),
+        to_tile(min.y, max_y),
+        to_tile(max.x, max_x),
+        to_tile(max.y, max_y),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use framebuffer::Framebuffer;
+    use geometry::{Derivatives, Matrix4};
+    use renderer;
+
+    // colors each fragment from its barycentric coordinates
+    struct BarycentricShader {
+        tint: u8,
+    }
+
+    impl Shader for BarycentricShader {
+        fn fragment(&self, bar: Vertex3<f32>, _derivatives: Derivatives, pixel: &mut Color) -> bool {
+            *pixel = Color::RGB((bar.x * 255.0) as u8, (bar.y * 255.0) as u8, self.tint);
+            true
+        }
+    }
+
+    fn clip_from_view() -> Matrix4 {
+        let eye = Vertex3::init(0.0, 0.0, 3.0);
+        renderer::viewport(0, 0, 200, 150, 255) * renderer::projection(eye, Vertex3::new())
+    }
+
+    fn frustum() -> Frustum {
+        Frustum::new(200, 150, &clip_from_view(), 3.0, 0.1, 100.0)
+    }
+
+    // a fan of overlapping triangles at different depths, some of them
+    // crossing the screen edges and the near plane
+    fn triangles() -> Vec<([Vertex4<f32>; 3], BarycentricShader)> {
+        let clip = |x: f32, y: f32, z: f32| clip_from_view() * Vertex3::init(x, y, z).to_homogeneous();
+        (0..24)
+            .map(|i| {
+                let t = i as f32 * 0.7;
+                let z = (i % 5) as f32 * 0.4 - 0.8;
+                let coords = [
+                    clip(t.cos() * 1.4, t.sin() * 1.4, z),
+                    clip((t + 2.0).cos() * 0.9, (t + 2.0).sin() * 0.9, z + 0.3),
+                    clip(0.1 * t.sin(), 0.1, if i % 7 == 0 { 3.5 } else { -z }),
+                ];
+                (coords, BarycentricShader { tint: i as u8 * 10 })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tiled_output_matches_single_threaded() {
+        let frustum = frustum();
+        let triangles = triangles();
+
+        let mut expected = Framebuffer::new(200, 150);
+        let mut expected_count = 0;
+        for (coords, shader) in triangles.iter() {
+            let interpolation = Interpolation::Perspective;
+            expected_count += renderer::clipped_triangle(&frustum, coords, interpolation, shader, &mut expected);
+        }
+
+        let mut actual = Framebuffer::new(200, 150);
+        let actual_count = render(&frustum, &triangles, Interpolation::Perspective, 4, &mut actual);
+
+        assert!(expected_count > 0);
+        assert!(actual_count == expected_count);
+        assert!(actual.pixels == expected.pixels);
+        assert!(actual.zbuffer == expected.zbuffer);
+    }
+
+    #[test]
+    fn tiled_render_of_an_empty_target_draws_nothing() {
+        let frustum = frustum();
+        let triangles = triangles();
+        assert!(render(&frustum, &triangles, Interpolation::Affine, 2, &mut Framebuffer::new(0, 150)) == 0);
+        assert!(render(&frustum, &triangles, Interpolation::Affine, 2, &mut Framebuffer::new(200, 0)) == 0);
+    }
+
+    #[test]
+    fn tiled_render_respects_existing_depth() {
+        let frustum = frustum();
+        let triangles = triangles();
+        let mut fb = Framebuffer::new(200, 150);
+        for z in fb.zbuffer.iter_mut() {
+            *z = f32::INFINITY;
+        }
+        assert!(render(&frustum, &triangles, Interpolation::Affine, 3, &mut fb) == 0);
+        assert!(fb.pixels.iter().all(|&pixel| pixel == Color::RGB(0, 0, 0)));
+    }
+}
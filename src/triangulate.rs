@@ -0,0 +1,133 @@
+use geometry::Vertex3;
+use std::vec::Vec;
+
+/// Splits a planar polygon into triangles, returned as indices into
+/// `polygon`. Convex polygons are split into a fan around the first corner,
+/// concave ones by ear clipping. Triangles keep the polygon's winding.
+pub fn triangulate(polygon: &[Vertex3<f32>]) -> Vec<[usize; 3]> {
+    let n = polygon.len();
+    if n < 3 {
+        return Vec::new();
+    }
+    let normal = polygon_normal(polygon);
+    if n == 3 || normal.norm() == 0.0 || is_convex(polygon, normal) {
+        return fan(&(0..n).collect::<Vec<_>>());
+    }
+    ear_clip(polygon, normal)
+}
+
+// Newell's method: the normal of a polygon that may not be perfectly planar
+// and whose first corners may be collinear. Its length is twice the area.
+fn polygon_normal(polygon: &[Vertex3<f32>]) -> Vertex3<f32> {
+    let mut normal = Vertex3::new();
+    for (i, current) in polygon.iter().enumerate() {
+        let next = polygon[(i + 1) % polygon.len()];
+        normal.x += (current.y - next.y) * (current.z + next.z);
+        normal.y += (current.z - next.z) * (current.x + next.x);
+        normal.z += (current.x - next.x) * (current.y + next.y);
+    }
+    normal
+}
+
+// How far the turn at `b` goes in the direction of the polygon's normal.
+// Positive for convex corners, negative for reflex ones.
+fn turn(a: Vertex3<f32>, b: Vertex3<f32>, c: Vertex3<f32>, normal: Vertex3<f32>) -> f32 {
+    Vertex3::cross(b - a, c - b) * normal
+}
+
+fn is_convex(polygon: &[Vertex3<f32>], normal: Vertex3<f32>) -> bool {
+    let n = polygon.len();
+    (0..n).all(|i| turn(polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n], normal) >= 0.0)
+}
+
+fn fan(corners: &[usize]) -> Vec<[usize; 3]> {
+    (1..corners.len() - 1)
+        .map(|i| [corners[0], corners[i], corners[i + 1]])
+        .collect()
+}
+
+fn contains(triangle: [Vertex3<f32>; 3], p: Vertex3<f32>, normal: Vertex3<f32>) -> bool {
+    let [a, b, c] = triangle;
+    Vertex3::cross(b - a, p - a) * normal >= 0.0
+        && Vertex3::cross(c - b, p - b) * normal >= 0.0
+        && Vertex3::cross(a - c, p - c) * normal >= 0.0
+}
+
+// Repeatedly cuts off a convex corner whose triangle holds no other corner
+// of the polygon. Falls back to a fan if the polygon is too degenerate for
+// any ear to be found.
+fn ear_clip(polygon: &[Vertex3<f32>], normal: Vertex3<f32>) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+    let mut triangles = Vec::with_capacity(polygon.len() - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            let triangle = [polygon[a], polygon[b], polygon[c]];
+            turn(triangle[0], triangle[1], triangle[2], normal) > 0.0
+                && !remaining
+                    .iter()
+                    .any(|&p| p != a && p != b && p != c && contains(triangle, polygon[p], normal))
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+    triangles.extend(fan(&remaining));
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat(points: &[(f32, f32)]) -> Vec<Vertex3<f32>> {
+        points.iter().map(|&(x, y)| Vertex3::init(x, y, 0.0)).collect()
+    }
+
+    // signed area of each triangle, positive for counter-clockwise
+    fn areas(polygon: &[Vertex3<f32>], triangles: &[[usize; 3]]) -> Vec<f32> {
+        triangles
+            .iter()
+            .map(|t| Vertex3::cross(polygon[t[1]] - polygon[t[0]], polygon[t[2]] - polygon[t[0]]).z / 2.0)
+            .collect()
+    }
+
+    #[test]
+    fn triangle_is_left_alone() {
+        let polygon = flat(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        assert!(triangulate(&polygon) == vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn convex_polygon_is_split_into_a_fan() {
+        let polygon = flat(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        assert!(triangulate(&polygon) == vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn concave_polygon_is_ear_clipped() {
+        // an arrowhead whose second corner points inwards
+        let polygon = flat(&[(0.0, 0.0), (2.0, 1.0), (4.0, 0.0), (2.0, 3.0)]);
+        let triangles = triangulate(&polygon);
+        assert!(triangles.len() == 2);
+        let areas = areas(&polygon, &triangles);
+        // a fan from the first corner would cover 8 instead of the real 4
+        assert!(areas.iter().all(|&area| area > 0.0));
+        assert!((areas.iter().sum::<f32>() - 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn clockwise_concave_polygon_keeps_its_winding() {
+        let polygon = flat(&[(2.0, 3.0), (4.0, 0.0), (2.0, 1.0), (0.0, 0.0), (-1.0, 2.0)]);
+        let triangles = triangulate(&polygon);
+        assert!(triangles.len() == 3);
+        let areas = areas(&polygon, &triangles);
+        assert!(areas.iter().all(|&area| area < 0.0));
+        assert!((areas.iter().sum::<f32>() + 7.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,146 @@
+use geometry::{Matrix4, Vertex3, Vertex4};
+use model::Model;
+use std::collections::HashMap;
+use std::vec::Vec;
+
+/// A corner shared by every face that uses the same position, texture
+/// coordinates and normal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vertex {
+    pub position: Vertex3<f32>,
+    pub uv: Vertex3<f32>,
+    pub normal: Vertex3<f32>,
+}
+
+/// The faces of a model as triangles of indices into a list of unique
+/// vertices, so that work done per vertex is done once however many faces
+/// share it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexBuffer {
+    pub vertices: Vec<Vertex>,
+    /// One triangle per face of the model, in the same order.
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl VertexBuffer {
+    /// Merges the corners of the model's faces that have the same position
+    /// index and the same texture coordinates and normal values, so faces
+    /// drawn flat only share vertices with faces in the same plane.
+    pub fn new(model: &Model) -> VertexBuffer {
+        let mut vertices = Vec::new();
+        let mut indices: HashMap<(u32, [u32; 5]), u32> = HashMap::new();
+        let triangles = model
+            .faces
+            .iter()
+            .map(|face| {
+                let mut triangle = [0; 3];
+                for (i, index) in triangle.iter_mut().enumerate() {
+                    let vertex = Vertex {
+                        position: model.vertex(face, i),
+                        uv: model.vertex_uv(face, i),
+                        normal: model.vertex_normal(face, i),
+                    };
+                    let (uv, normal) = (vertex.uv, vertex.normal);
+                    let bits = [uv.x, uv.y, normal.x, normal.y, normal.z].map(f32::to_bits);
+                    *index = *indices.entry((face.get_vertex(i), bits)).or_insert_with(|| {
+                        vertices.push(vertex);
+                        vertices.len() as u32 - 1
+                    });
+                }
+                triangle
+            })
+            .collect();
+        VertexBuffer { vertices, triangles }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+}
+
+/// Every vertex of a buffer put through one transform, computed once per
+/// frame and looked up per triangle.
+pub struct TransformCache {
+    pub coords: Vec<Vertex4<f32>>,
+}
+
+impl TransformCache {
+    pub fn new(buffer: &VertexBuffer, transform: &Matrix4) -> TransformCache {
+        TransformCache {
+            coords: buffer
+                .vertices
+                .iter()
+                .map(|vertex| transform.clone() * vertex.position.to_homogeneous())
+                .collect(),
+        }
+    }
+
+    /// The transformed corners of a triangle of the buffer.
+    pub fn triangle(&self, triangle: [u32; 3]) -> [Vertex4<f32>; 3] {
+        triangle.map(|index| self.coords[index as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use normals::NormalSettings;
+
+    #[test]
+    fn shared_corners_become_one_vertex() {
+        // a square with smooth normals and a flat triangle hanging off it
+        let model = Model::from_reader(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 -1\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\ns off\nf 1 5 2\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let buffer = VertexBuffer::new(&model);
+        assert!(buffer.triangles == vec![[0, 1, 2], [0, 2, 3], [4, 5, 6]]);
+        // the flat face's corners differ from the square's in uv and normal
+        assert!(buffer.len() == 7);
+        assert!(buffer.vertices[4].position == Vertex3::init(0.0, 0.0, 0.0));
+        assert!(buffer.vertices[4].normal == Vertex3::init(0.0, -1.0, 0.0));
+        assert!(buffer.vertices[2].uv == Vertex3::init(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn transform_cache_transforms_each_vertex() {
+        // the square's diagonal corners are shared, the flat face's are not
+        let model = Model::from_reader(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 -1\nf 1 2 3 4\ns off\nf 1 5 2\n".as_bytes(),
+        )
+        .unwrap();
+        let buffer = model.vertex_buffer();
+        let mut transform = Matrix4::identity();
+        transform.set(0, 3, 2.0);
+        transform.set(1, 1, 3.0);
+        transform.set(3, 2, -0.5);
+        let cache = TransformCache::new(buffer, &transform);
+        assert!(cache.coords.len() == buffer.len());
+        for (coord, vertex) in cache.coords.iter().zip(buffer.vertices.iter()) {
+            assert!(*coord == transform.clone() * vertex.position.to_homogeneous());
+        }
+        // and each triangle finds the corners of its face
+        for (face, &triangle) in model.faces.iter().zip(buffer.triangles.iter()) {
+            let corners = cache.triangle(triangle);
+            for (i, corner) in corners.iter().enumerate() {
+                assert!(*corner == transform.clone() * model.vertex(face, i).to_homogeneous());
+            }
+        }
+    }
+
+    #[test]
+    fn generating_normals_rebuilds_the_buffer() {
+        let mut model = Model::from_reader("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".as_bytes()).unwrap();
+        assert!(model.vertex_buffer().vertices[0].normal == Vertex3::init(0.0, 0.0, 1.0));
+        model.faces[0].vertexes = [0, 2, 1];
+        model.faces[0].normals = None;
+        model.generate_normals(NormalSettings::new());
+        assert!(model.vertex_buffer().vertices[0].normal == Vertex3::init(0.0, 0.0, -1.0));
+    }
+}